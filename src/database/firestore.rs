@@ -1,36 +1,311 @@
-use nalgebra::Point3;
-use procedural_generation::id_types::Ship;
-use std::sync::mpsc;
-use std::sync::mpsc::{Receiver, Sender};
-use std::thread;
-use std::thread::JoinHandle;
+use crate::database::{DatabaseError, Store};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::env;
+use std::time::{Duration, Instant};
 
-pub enum SystemMessage {
-    UpdatePosition(Ship, Point3<f64>),
+const FIRESTORE_URL: &str = "https://firestore.googleapis.com/v1";
+const METADATA_TOKEN_URL: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Firestore REST API, or its emulator.
+pub struct Firestore {
+    agent: ureq::Agent,
+    documents_name: String,
+    documents_url: String,
+    credentials: Credentials,
 }
 
-pub struct System {
-    channel: Receiver<SystemMessage>,
+impl Firestore {
+    pub fn production(project_id: &str) -> Self {
+        Self::new(FIRESTORE_URL, project_id, Credentials::from_env())
+    }
+
+    pub fn emulator(host: &str, project_id: &str) -> Self {
+        Self::new(
+            &format!("http://{}/v1", host),
+            project_id,
+            Credentials::Emulator,
+        )
+    }
+
+    fn new(base_url: &str, project_id: &str, credentials: Credentials) -> Self {
+        let documents_name = format!("projects/{}/databases/(default)/documents", project_id);
+
+        Self {
+            agent: ureq::agent(),
+            documents_url: format!("{}/{}", base_url, documents_name),
+            documents_name,
+            credentials,
+        }
+    }
 }
 
-impl System {
-    pub fn init() -> (JoinHandle<()>, Sender<SystemMessage>) {
-        let (tx, rx) = mpsc::channel();
+impl Store for Firestore {
+    fn get(
+        &mut self,
+        collection: &str,
+        document: &str,
+    ) -> Result<Option<Map<String, Value>>, DatabaseError> {
+        let token = self.credentials.token(&self.agent)?;
 
-        let mut system = Self { channel: rx };
+        let url = format!("{}/{}/{}", self.documents_url, collection, document);
 
-        let handle = thread::spawn(move || {
-            system.update_loop();
-        });
+        let response = self
+            .agent
+            .get(&url)
+            .timeout(REQUEST_TIMEOUT)
+            .set("Authorization", &format!("Bearer {}", token))
+            .call();
+
+        if response.synthetic_error().is_none() && response.status() == 404 {
+            return Ok(None);
+        }
+
+        let mut body = check(response)?
+            .into_json()
+            .map_err(|error| DatabaseError::Transport(error.to_string()))?;
+
+        //A document without any field has no fields member
+        let fields = match body.get_mut("fields").map(Value::take) {
+            Some(Value::Object(fields)) => decode_fields(fields),
+            _ => Map::new(),
+        };
+
+        Ok(Some(fields))
+    }
+
+    fn commit(
+        &mut self,
+        collection: &str,
+        writes: Vec<(String, Map<String, Value>)>,
+    ) -> Result<(), DatabaseError> {
+        let token = self.credentials.token(&self.agent)?;
+
+        let writes = writes
+            .into_iter()
+            .map(|(document, fields)| {
+                let field_paths = fields.keys().cloned().collect::<Vec<String>>();
 
-        (handle, tx)
+                json!({
+                    "update": {
+                        "name": format!("{}/{}/{}", self.documents_name, collection, document),
+                        "fields": encode_fields(fields),
+                    },
+                    "updateMask": { "fieldPaths": field_paths },
+                })
+            })
+            .collect::<Vec<Value>>();
+
+        let url = format!("{}:commit", self.documents_url);
+
+        let response = self
+            .agent
+            .post(&url)
+            .timeout(REQUEST_TIMEOUT)
+            .set("Authorization", &format!("Bearer {}", token))
+            .send_json(json!({ "writes": writes }));
+
+        check(response).map(|_| ())
+    }
+}
+
+fn check(response: ureq::Response) -> Result<ureq::Response, DatabaseError> {
+    if let Some(error) = response.synthetic_error() {
+        return Err(DatabaseError::Transport(error.to_string()));
+    }
+
+    if response.error() {
+        let status = response.status();
+
+        return Err(DatabaseError::Http(
+            status,
+            response.into_string().unwrap_or_default(),
+        ));
     }
 
-    fn update_loop(&mut self) {
-        while let Ok(result) = self.channel.recv() {
-            match result {
-                SystemMessage::UpdatePosition(ship_id, position) => {}
+    Ok(response)
+}
+
+enum Credentials {
+    Emulator,
+    Static(String),
+    Metadata(Option<(String, Instant)>),
+}
+
+#[derive(Deserialize)]
+struct AccessToken {
+    access_token: String,
+    expires_in: u64,
+}
+
+impl Credentials {
+    //A token can be given directly, otherwise it's requested from the instance metadata server
+    fn from_env() -> Self {
+        match env::var("FIRESTORE_ACCESS_TOKEN") {
+            Ok(token) => Credentials::Static(token),
+            Err(_) => Credentials::Metadata(None),
+        }
+    }
+
+    fn token(&mut self, agent: &ureq::Agent) -> Result<String, DatabaseError> {
+        match self {
+            Credentials::Emulator => Ok("owner".to_string()),
+            Credentials::Static(token) => Ok(token.clone()),
+            Credentials::Metadata(cached) => {
+                if let Some((token, expiry)) = cached {
+                    if Instant::now() < *expiry {
+                        return Ok(token.clone());
+                    }
+                }
+
+                let response = agent
+                    .get(METADATA_TOKEN_URL)
+                    .set("Metadata-Flavor", "Google")
+                    .timeout(REQUEST_TIMEOUT)
+                    .call();
+
+                let token = check(response)?
+                    .into_json_deserialize::<AccessToken>()
+                    .map_err(|error| DatabaseError::Credentials(error.to_string()))?;
+
+                //Refreshed a minute before it expires
+                let expiry =
+                    Instant::now() + Duration::from_secs(token.expires_in.saturating_sub(60));
+
+                *cached = Some((token.access_token.clone(), expiry));
+
+                Ok(token.access_token)
             }
         }
     }
 }
+
+//Plain JSON to the typed values of the Firestore REST API
+fn encode(value: Value) -> Value {
+    match value {
+        Value::Null => json!({ "nullValue": null }),
+        Value::Bool(boolean) => json!({ "booleanValue": boolean }),
+        Value::Number(number) => {
+            if number.is_f64() {
+                json!({ "doubleValue": number })
+            } else {
+                json!({ "integerValue": number.to_string() })
+            }
+        }
+        Value::String(string) => json!({ "stringValue": string }),
+        Value::Array(values) => {
+            let values = values.into_iter().map(encode).collect::<Vec<Value>>();
+
+            json!({ "arrayValue": { "values": values } })
+        }
+        Value::Object(fields) => json!({ "mapValue": { "fields": encode_fields(fields) } }),
+    }
+}
+
+fn encode_fields(fields: Map<String, Value>) -> Map<String, Value> {
+    fields
+        .into_iter()
+        .map(|(name, value)| (name, encode(value)))
+        .collect()
+}
+
+//Typed values of the Firestore REST API back to plain JSON
+fn decode(value: Value) -> Value {
+    let (kind, mut value) = match value {
+        Value::Object(typed) => match typed.into_iter().next() {
+            Some(typed) => typed,
+            None => return Value::Null,
+        },
+        _ => return Value::Null,
+    };
+
+    match kind.as_str() {
+        "integerValue" => match value.as_str().map(str::parse::<i64>) {
+            Some(Ok(integer)) => Value::from(integer),
+            _ => Value::Null,
+        },
+        //Empty arrays and maps have no values nor fields member
+        "arrayValue" => match value.get_mut("values").map(Value::take) {
+            Some(Value::Array(values)) => Value::Array(values.into_iter().map(decode).collect()),
+            _ => Value::Array(Vec::new()),
+        },
+        "mapValue" => match value.get_mut("fields").map(Value::take) {
+            Some(Value::Object(fields)) => Value::Object(decode_fields(fields)),
+            _ => Value::Object(Map::new()),
+        },
+        "nullValue" => Value::Null,
+        _ => value,
+    }
+}
+
+fn decode_fields(fields: Map<String, Value>) -> Map<String, Value> {
+    fields
+        .into_iter()
+        .map(|(name, value)| (name, decode(value)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inventory::encoding;
+    use procedural_generation::id_types::Module;
+    use procedural_generation::modules::samplers::SamplerStats;
+
+    //Firestore refuses an array value directly inside another array value
+    fn has_nested_arrays(value: &Value) -> bool {
+        match value
+            .pointer("/arrayValue/values")
+            .and_then(Value::as_array)
+        {
+            Some(values) => values
+                .iter()
+                .any(|element| element.get("arrayValue").is_some() || has_nested_arrays(element)),
+            None => match value {
+                Value::Object(fields) => fields.values().any(has_nested_arrays),
+                Value::Array(values) => values.iter().any(has_nested_arrays),
+                _ => false,
+            },
+        }
+    }
+
+    #[test]
+    fn encoded_inventory_has_no_nested_arrays() {
+        let module_id =
+            serde_json::from_value::<Module>(json!({ "Sampler": 1 })).expect("Invalid module id");
+        let requirements = match SamplerStats::from_properties(&[0; 5]) {
+            Ok(stats) => stats.get_requirements(),
+            Err(_) => panic!("Invalid sampler properties"),
+        };
+        let (resource_id, quantity) = requirements[0];
+
+        let stored = json!({
+            "version": encoding::VERSION,
+            "inventory": {
+                "modules": { "modules": [{
+                    "id": module_id,
+                    "value": {
+                        "name": "Sampler",
+                        "creator": 1,
+                        "properties": [0, 0, 0, 0, 0],
+                        "resources": {
+                            "resource_ids": [resource_id],
+                            "quantities": [quantity],
+                            "crafted": [quantity],
+                        },
+                    },
+                }]},
+                "resources": { "resources": [{ "id": resource_id, "value": 5 }] },
+            },
+        });
+
+        let inventory = encoding::from_json(stored).unwrap_or_else(|error| panic!("{}", error));
+
+        let encoded = encode(encoding::to_json(&inventory));
+
+        assert!(encoded.pointer("/mapValue/fields/inventory").is_some());
+        assert!(!has_nested_arrays(&encoded));
+    }
+}
@@ -0,0 +1,104 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    document: String,
+    fields: Map<String, Value>,
+}
+
+// Local file of the writes not yet committed to the database, one JSON entry per line.
+pub struct Journal {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl Journal {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path, file: None }
+    }
+
+    //Entries in write order, a line torn by a crash is skipped
+    pub fn read(&self) -> io::Result<Vec<(String, Map<String, Value>)>> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(error) => return Err(error),
+        };
+
+        let mut entries = Vec::new();
+
+        for line in BufReader::new(file).lines() {
+            if let Ok(entry) = serde_json::from_str::<Entry>(&line?) {
+                entries.push((entry.document, entry.fields));
+            }
+        }
+
+        Ok(entries)
+    }
+
+    //Returns once the OS has the entry, a crash of the worker doesn't lose it
+    pub fn append(&mut self, document: &str, fields: &Map<String, Value>) -> io::Result<()> {
+        if self.file.is_none() {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+
+            self.file = Some(file);
+        }
+
+        let entry = Entry {
+            document: document.to_string(),
+            fields: fields.clone(),
+        };
+
+        let mut line = serde_json::to_vec(&entry)?;
+
+        line.push(b'\n');
+
+        self.file
+            .as_mut()
+            .expect("Journal file is open")
+            .write_all(&line)
+    }
+
+    //Replaces the entries, the old file is kept until the new one is complete
+    pub fn rewrite<'a, I>(&mut self, entries: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = (&'a String, &'a Map<String, Value>)>,
+    {
+        self.file = None;
+
+        let temporary = self.path.with_extension("tmp");
+
+        let mut writer = BufWriter::new(File::create(&temporary)?);
+
+        for (document, fields) in entries {
+            let entry = Entry {
+                document: document.clone(),
+                fields: fields.clone(),
+            };
+
+            serde_json::to_writer(&mut writer, &entry)?;
+            writer.write_all(b"\n")?;
+        }
+
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+
+        fs::rename(&temporary, &self.path)
+    }
+
+    pub fn clear(&mut self) -> io::Result<()> {
+        self.file = None;
+
+        match fs::remove_file(&self.path) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use crate::database::{DatabaseError, Store};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, PoisonError, RwLock};
+
+// In-process document store, stands in for Firestore when running offline.
+pub struct MemoryStore {
+    documents: RwLock<HashMap<(String, String), Map<String, Value>>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self {
+            documents: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, collection: &str, document: &str) -> Option<Map<String, Value>> {
+        let documents = self
+            .documents
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let key = (collection.to_string(), document.to_string());
+
+        documents.get(&key).cloned()
+    }
+
+    //Fields are merged into the document, like a Firestore patch with an update mask
+    pub fn patch(&self, collection: &str, document: &str, fields: Map<String, Value>) {
+        let mut documents = self
+            .documents
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let key = (collection.to_string(), document.to_string());
+
+        documents.entry(key).or_default().extend(fields);
+    }
+}
+
+//Shared so the documents outlive a restart of the database system
+impl Store for Arc<MemoryStore> {
+    fn get(
+        &mut self,
+        collection: &str,
+        document: &str,
+    ) -> Result<Option<Map<String, Value>>, DatabaseError> {
+        Ok(MemoryStore::get(self, collection, document))
+    }
+
+    fn commit(
+        &mut self,
+        collection: &str,
+        writes: Vec<(String, Map<String, Value>)>,
+    ) -> Result<(), DatabaseError> {
+        for (document, fields) in writes {
+            self.patch(collection, &document, fields);
+        }
+
+        Ok(())
+    }
+}
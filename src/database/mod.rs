@@ -1 +1,625 @@
 pub mod firestore;
+pub mod journal;
+pub mod memory;
+pub mod sqlite;
+
+use crate::database::firestore::Firestore;
+use crate::database::journal::Journal;
+use crate::database::memory::MemoryStore;
+use crate::database::sqlite::Sqlite;
+use crate::inventory::encoding::{self, EncodingError};
+use crate::inventory::Inventory;
+use crate::spatial_os::connexion::Op;
+use crate::systems;
+use crate::systems::config::Config;
+use crate::systems::mailbox::{Mailbox, Overflow};
+use crate::systems::registry::Registry;
+use nalgebra::Point3;
+use procedural_generation::id_types::{Asteroid, Ship, User};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::env;
+use std::fmt;
+use std::mem;
+use std::num::NonZeroU32;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+const SHIPS: &str = "ships";
+
+//Each message writes one field of the ship document
+pub enum SystemMessage {
+    LoadShip(Ship),
+    UpdatePosition(Ship, Point3<f64>),
+    UpdateInventory(Ship, Inventory),
+    UpdateHealth(Ship, NonZeroU32),
+    UpdateOwner(Ship, User),
+    UpdateAsteroid(Ship, Asteroid),
+    UpdateCraftingLevels(Ship, [u8; 5]),
+    UpdateCraftingExperience(Ship, [u32; 5]),
+}
+
+impl SystemMessage {
+    fn ship_id(&self) -> &Ship {
+        match self {
+            SystemMessage::LoadShip(ship_id) => ship_id,
+            SystemMessage::UpdatePosition(ship_id, _) => ship_id,
+            SystemMessage::UpdateInventory(ship_id, _) => ship_id,
+            SystemMessage::UpdateHealth(ship_id, _) => ship_id,
+            SystemMessage::UpdateOwner(ship_id, _) => ship_id,
+            SystemMessage::UpdateAsteroid(ship_id, _) => ship_id,
+            SystemMessage::UpdateCraftingLevels(ship_id, _) => ship_id,
+            SystemMessage::UpdateCraftingExperience(ship_id, _) => ship_id,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum Target {
+    Firestore,
+    Emulator(String),
+    Memory(Arc<MemoryStore>),
+    Sqlite(PathBuf),
+}
+
+#[derive(Clone)]
+pub struct Settings {
+    pub project_id: String,
+    pub target: Target,
+    pub journal: PathBuf,
+}
+
+impl Settings {
+    //Same environment variables as the Google Cloud tools
+    pub fn from_env() -> Self {
+        let project_id =
+            env::var("GOOGLE_CLOUD_PROJECT").unwrap_or_else(|_| "ship-worker".to_string());
+
+        let target = if let Ok(host) = env::var("FIRESTORE_EMULATOR_HOST") {
+            Target::Emulator(host)
+        } else if let Ok(database) = env::var("SHIP_WORKER_DATABASE") {
+            //"firestore", "memory" or "sqlite:<path>"
+            match database.as_str() {
+                "firestore" => Target::Firestore,
+                "memory" => Target::Memory(Arc::new(MemoryStore::new())),
+                _ if database.starts_with("sqlite:") && database.len() > "sqlite:".len() => {
+                    Target::Sqlite(database.trim_start_matches("sqlite:").into())
+                }
+                //Writing to production by mistake is worse than not starting
+                _ => panic!(
+                    "Unknown SHIP_WORKER_DATABASE {}, expected firestore, memory or sqlite:<path>",
+                    database
+                ),
+            }
+        } else {
+            Target::Firestore
+        };
+
+        let journal = env::var("SHIP_WORKER_JOURNAL")
+            .unwrap_or_else(|_| "database.journal".to_string())
+            .into();
+
+        Self {
+            project_id,
+            target,
+            journal,
+        }
+    }
+}
+
+// Persisted components of a ship, None when never written.
+pub struct ShipState {
+    pub position: Option<Point3<f64>>,
+    pub inventory: Option<Inventory>,
+    pub health: Option<NonZeroU32>,
+    pub owner: Option<User>,
+    pub asteroid: Option<Asteroid>,
+    pub crafting_levels: Option<[u8; 5]>,
+    pub crafting_experience: Option<[u32; 5]>,
+}
+
+#[derive(Deserialize)]
+struct ShipDocument {
+    position: Option<Position>,
+    inventory: Option<Value>,
+    health: Option<NonZeroU32>,
+    owner: Option<User>,
+    asteroid: Option<Asteroid>,
+    crafting_levels: Option<[u8; 5]>,
+    crafting_experience: Option<[u32; 5]>,
+}
+
+#[derive(Deserialize)]
+struct Position {
+    x: f64,
+    y: f64,
+    z: f64,
+}
+
+impl TryFrom<ShipDocument> for ShipState {
+    type Error = EncodingError;
+
+    fn try_from(document: ShipDocument) -> Result<Self, EncodingError> {
+        let inventory = match document.inventory {
+            Some(inventory) => Some(encoding::from_json(inventory)?),
+            None => None,
+        };
+
+        Ok(Self {
+            position: document
+                .position
+                .map(|position| Point3::new(position.x, position.y, position.z)),
+            inventory,
+            health: document.health,
+            owner: document.owner,
+            asteroid: document.asteroid,
+            crafting_levels: document.crafting_levels,
+            crafting_experience: document.crafting_experience,
+        })
+    }
+}
+
+pub enum DatabaseError {
+    Transport(String),
+    Http(u16, String),
+    Credentials(String),
+    Sqlite(String),
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DatabaseError::Transport(message) => write!(f, "transport error: {}", message),
+            DatabaseError::Http(status, body) => write!(f, "HTTP {}: {}", status, body),
+            DatabaseError::Credentials(message) => write!(f, "no access token: {}", message),
+            DatabaseError::Sqlite(message) => write!(f, "SQLite error: {}", message),
+        }
+    }
+}
+
+impl DatabaseError {
+    //Outages and overloads, not writes the database refuses
+    fn is_retryable(&self) -> bool {
+        match self {
+            DatabaseError::Http(status, _) => *status >= 500 || *status == 408 || *status == 429,
+            _ => true,
+        }
+    }
+}
+
+// Document database the ship state is persisted in.
+pub trait Store: Send {
+    fn get(
+        &mut self,
+        collection: &str,
+        document: &str,
+    ) -> Result<Option<Map<String, Value>>, DatabaseError>;
+
+    //All writes succeed or none, only the given fields of each document are written
+    fn commit(
+        &mut self,
+        collection: &str,
+        writes: Vec<(String, Map<String, Value>)>,
+    ) -> Result<(), DatabaseError>;
+}
+
+pub struct System {
+    dispatcher: Mailbox<Op>,
+
+    store: Box<dyn Store>,
+    journal: Journal,
+
+    //Fields written since the last commit by document, only the latest value of each is kept
+    dirty: HashMap<String, Map<String, Value>>,
+    flush_interval: Duration,
+    flush_size: usize,
+    failing: bool,
+
+    //Ships that couldn't be read, retried once writes succeed again
+    loads: Vec<Ship>,
+}
+
+impl systems::System for System {
+    type Message = SystemMessage;
+
+    const NAME: &'static str = "database";
+
+    fn init(registry: &Registry) -> Self {
+        let config = registry.get::<Config>();
+        let settings = registry.get::<Settings>();
+
+        let store: Box<dyn Store> = match settings.target {
+            Target::Firestore => Box::new(Firestore::production(&settings.project_id)),
+            Target::Emulator(host) => Box::new(Firestore::emulator(&host, &settings.project_id)),
+            Target::Memory(store) => Box::new(store),
+            Target::Sqlite(path) => match Sqlite::open(&path) {
+                Ok(sqlite) => Box::new(sqlite),
+                Err(error) => panic!("Can't open {}: {}", path.display(), error),
+            },
+        };
+
+        let journal = Journal::new(settings.journal);
+
+        //Writes left uncommitted by a crash or an outage
+        let mut dirty: HashMap<String, Map<String, Value>> =
+            HashMap::with_capacity(config.flush_size);
+
+        match journal.read() {
+            Ok(entries) => {
+                for (document, fields) in entries {
+                    dirty.entry(document).or_default().extend(fields);
+                }
+            }
+            Err(error) => println!("Journal read failed: {}", error),
+        }
+
+        Self {
+            dispatcher: registry.get::<Mailbox<Op>>(),
+
+            store,
+            journal,
+
+            dirty,
+            flush_interval: config.flush_interval,
+            flush_size: config.flush_size,
+            failing: false,
+
+            loads: Vec::new(),
+        }
+    }
+
+    fn overflow() -> Overflow<SystemMessage> {
+        //Only the latest value of a field is worth writing
+        Overflow::Coalesce(|queued, message| {
+            mem::discriminant(queued) == mem::discriminant(message)
+                && queued.ship_id() == message.ship_id()
+        })
+    }
+
+    fn handle_message(&mut self, message: SystemMessage) {
+        match message {
+            SystemMessage::LoadShip(ship_id) => self.load_ship(&ship_id),
+            SystemMessage::UpdatePosition(ship_id, position) => {
+                self.update_position(&ship_id, &position)
+            }
+            SystemMessage::UpdateInventory(ship_id, inventory) => {
+                self.update_field(&ship_id, "inventory", &encoding::to_json(&inventory))
+            }
+            SystemMessage::UpdateHealth(ship_id, health) => {
+                self.update_field(&ship_id, "health", &health)
+            }
+            SystemMessage::UpdateOwner(ship_id, user_id) => {
+                self.update_field(&ship_id, "owner", &user_id)
+            }
+            SystemMessage::UpdateAsteroid(ship_id, asteroid_id) => {
+                self.update_field(&ship_id, "asteroid", &asteroid_id)
+            }
+            SystemMessage::UpdateCraftingLevels(ship_id, levels) => {
+                self.update_field(&ship_id, "crafting_levels", &levels)
+            }
+            SystemMessage::UpdateCraftingExperience(ship_id, experience) => {
+                self.update_field(&ship_id, "crafting_experience", &experience)
+            }
+        }
+    }
+
+    fn frame_time(&self) -> Option<Duration> {
+        Some(self.flush_interval)
+    }
+
+    fn tick(&mut self) {
+        self.flush();
+
+        if !self.failing {
+            for ship_id in mem::take(&mut self.loads) {
+                self.load_ship(&ship_id);
+            }
+        }
+    }
+
+    fn shutdown(&mut self) {
+        self.flush();
+    }
+}
+
+impl System {
+    //Writes sent before are in the queue ahead or not committed yet, the state read is the latest
+    fn load_ship(&mut self, ship_id: &Ship) {
+        let document = document_id(ship_id);
+
+        //Starting from nothing would overwrite the persisted state, the ship waits instead
+        let mut fields = match self.store.get(SHIPS, &document) {
+            Ok(fields) => fields.unwrap_or_default(),
+            Err(error) => {
+                println!("Database read failed: {}", error);
+                self.loads.push(*ship_id);
+                return;
+            }
+        };
+
+        if let Some(dirty) = self.dirty.get(&document) {
+            fields.extend(dirty.clone());
+        }
+
+        let state = match serde_json::from_value::<ShipDocument>(Value::Object(fields)) {
+            Ok(document) => ShipState::try_from(document).map_err(|error| error.to_string()),
+            Err(error) => Err(error.to_string()),
+        };
+
+        //Defaults would be written over the stored progress, the ship isn't added instead
+        match state {
+            Ok(state) => self.dispatcher.send(Op::ShipLoaded(*ship_id, state)),
+            Err(error) => {
+                println!(
+                    "Invalid ship document {}, ship rejected: {}",
+                    document, error
+                );
+
+                self.dispatcher.send(Op::ShipRejected(*ship_id));
+            }
+        }
+    }
+
+    fn update_position(&mut self, ship_id: &Ship, position: &Point3<f64>) {
+        let mut fields = Map::new();
+
+        fields.insert(
+            "position".to_string(),
+            json!({ "x": position.x, "y": position.y, "z": position.z }),
+        );
+
+        self.patch(ship_id, fields);
+    }
+
+    fn update_field<T: Serialize>(&mut self, ship_id: &Ship, name: &str, value: &T) {
+        let value = match serde_json::to_value(value) {
+            Ok(value) => value,
+            Err(error) => {
+                println!("Can't serialize {}: {}", name, error);
+                return;
+            }
+        };
+
+        let mut fields = Map::new();
+
+        fields.insert(name.to_string(), value);
+
+        self.patch(ship_id, fields);
+    }
+
+    fn patch(&mut self, ship_id: &Ship, fields: Map<String, Value>) {
+        let document = document_id(ship_id);
+
+        //Journaled before being buffered, the journal is cleared once the writes are committed
+        if let Err(error) = self.journal.append(&document, &fields) {
+            println!("Journal write failed: {}", error);
+        }
+
+        self.dirty.entry(document).or_default().extend(fields);
+
+        //While the database is unreachable, only the timer retries
+        if !self.failing && self.dirty.len() >= self.flush_size {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.dirty.is_empty() {
+            return;
+        }
+
+        while !self.dirty.is_empty() {
+            let documents = self
+                .dirty
+                .keys()
+                .take(self.flush_size)
+                .cloned()
+                .collect::<Vec<String>>();
+
+            let writes = documents
+                .iter()
+                .map(|document| (document.clone(), self.dirty[document].clone()))
+                .collect::<Vec<(String, Map<String, Value>)>>();
+
+            let result = match self.store.commit(SHIPS, writes) {
+                Err(error) if !error.is_retryable() => {
+                    println!(
+                        "Database rejected {} ships, writing them one by one: {}",
+                        documents.len(),
+                        error
+                    );
+
+                    self.commit_each(&documents)
+                }
+                result => result,
+            };
+
+            if let Err(error) = result {
+                self.fail(error);
+                return;
+            }
+
+            for document in documents {
+                self.dirty.remove(&document);
+            }
+        }
+
+        self.failing = false;
+
+        if let Err(error) = self.journal.clear() {
+            println!("Journal write failed: {}", error);
+        }
+    }
+
+    //A rejected batch is written again one document at a time, the documents refused alone are dropped
+    fn commit_each(&mut self, documents: &[String]) -> Result<(), DatabaseError> {
+        for document in documents {
+            let fields = match self.dirty.get(document) {
+                Some(fields) => fields.clone(),
+                None => continue,
+            };
+
+            match self.store.commit(SHIPS, vec![(document.clone(), fields)]) {
+                Ok(()) => {}
+                Err(error) if error.is_retryable() => return Err(error),
+                Err(error) => println!(
+                    "Database rejected ship {}, write dropped: {}",
+                    document, error
+                ),
+            }
+
+            self.dirty.remove(document);
+        }
+
+        Ok(())
+    }
+
+    fn fail(&mut self, error: DatabaseError) {
+        println!(
+            "Database write failed, {} ships kept in the journal: {}",
+            self.dirty.len(),
+            error
+        );
+
+        self.failing = true;
+
+        //Compacted on each retry, only the latest value of each field is kept
+        if let Err(error) = self.journal.rewrite(self.dirty.iter()) {
+            println!("Journal write failed: {}", error);
+        }
+    }
+}
+
+//Ship ids serialize to a number, used as the document name
+fn document_id(ship_id: &Ship) -> String {
+    serde_json::to_string(ship_id)
+        .expect("Ship id serialization failed")
+        .trim_matches('"')
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::systems::mailbox::Envelope;
+    use crate::systems::System as _;
+    use std::fs;
+    use std::process;
+
+    fn registry(name: &str, store: &Arc<MemoryStore>) -> Registry {
+        let journal =
+            env::temp_dir().join(format!("ship-worker-{}-{}.journal", name, process::id()));
+
+        fs::remove_file(&journal).ok();
+
+        let mut registry = Registry::new();
+
+        registry.insert(Config::default());
+        registry.insert(Settings {
+            project_id: String::from("test"),
+            target: Target::Memory(store.clone()),
+            journal,
+        });
+        registry.insert(Mailbox::<Op>::new(4, Overflow::Block));
+
+        registry
+    }
+
+    fn journaled(registry: &Registry) -> usize {
+        match Journal::new(registry.get::<Settings>().journal).read() {
+            Ok(entries) => entries.len(),
+            Err(error) => panic!("Journal read failed: {}", error),
+        }
+    }
+
+    #[test]
+    fn undecodable_ship_is_rejected_and_left_untouched() {
+        let store = Arc::new(MemoryStore::new());
+        let registry = registry("rejected", &store);
+        let ship_id = serde_json::from_value::<Ship>(json!(1)).expect("Invalid ship id");
+        let receiver = registry.get::<Mailbox<Op>>().receiver();
+
+        let mut fields = Map::new();
+
+        fields.insert(String::from("health"), json!(10));
+        fields.insert(
+            String::from("inventory"),
+            json!({ "version": 99, "inventory": {} }),
+        );
+
+        store.patch(SHIPS, &document_id(&ship_id), fields.clone());
+
+        let mut system = System::init(&registry);
+
+        system.handle_message(SystemMessage::LoadShip(ship_id));
+        system.tick();
+
+        assert!(matches!(
+            receiver.try_recv(),
+            Some(Envelope::Message(Op::ShipRejected(rejected))) if rejected == ship_id
+        ));
+        assert!(MemoryStore::get(&store, SHIPS, &document_id(&ship_id)) == Some(fields));
+    }
+
+    #[test]
+    fn owner_and_asteroid_are_loaded_back() {
+        let store = Arc::new(MemoryStore::new());
+        let registry = registry("owner", &store);
+        let ship_id = serde_json::from_value::<Ship>(json!(1)).expect("Invalid ship id");
+        let user_id = serde_json::from_value::<User>(json!(2)).expect("Invalid user id");
+        let asteroid_id =
+            serde_json::from_value::<Asteroid>(json!(3)).expect("Invalid asteroid id");
+        let receiver = registry.get::<Mailbox<Op>>().receiver();
+
+        let mut system = System::init(&registry);
+
+        system.handle_message(SystemMessage::UpdateOwner(ship_id, user_id));
+        system.handle_message(SystemMessage::UpdateAsteroid(ship_id, asteroid_id));
+        system.tick();
+
+        //Read from the store, nothing left to overlay
+        let mut system = System::init(&registry);
+
+        system.handle_message(SystemMessage::LoadShip(ship_id));
+
+        let state = match receiver.try_recv() {
+            Some(Envelope::Message(Op::ShipLoaded(_, state))) => state,
+            _ => panic!("Ship not loaded"),
+        };
+
+        assert!(state.owner == Some(user_id));
+        assert!(state.asteroid == Some(asteroid_id));
+    }
+
+    #[test]
+    fn buffered_writes_survive_a_crash_until_committed() {
+        let store = Arc::new(MemoryStore::new());
+        let registry = registry("crash", &store);
+        let ship_id = serde_json::from_value::<Ship>(json!(1)).expect("Invalid ship id");
+        let health = NonZeroU32::new(10).expect("Zero health");
+
+        let mut system = System::init(&registry);
+
+        system.handle_message(SystemMessage::UpdateHealth(ship_id, health));
+
+        assert_eq!(journaled(&registry), 1);
+
+        //Crashed before the flush interval
+        drop(system);
+
+        let mut system = System::init(&registry);
+
+        system.tick();
+
+        let stored = MemoryStore::get(&store, SHIPS, &document_id(&ship_id));
+
+        assert_eq!(
+            stored.and_then(|fields| fields.get("health").cloned()),
+            Some(json!(10))
+        );
+        assert_eq!(journaled(&registry), 0);
+    }
+}
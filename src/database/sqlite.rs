@@ -0,0 +1,95 @@
+use crate::database::{DatabaseError, Store};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_json::{Map, Value};
+use std::path::Path;
+
+// Documents stored as JSON in a local SQLite file, for development and small servers.
+pub struct Sqlite {
+    connection: Connection,
+}
+
+impl Sqlite {
+    pub fn open(path: &Path) -> Result<Self, DatabaseError> {
+        let connection = Connection::open(path).map_err(sqlite_error)?;
+
+        connection
+            .execute_batch(
+                "CREATE TABLE IF NOT EXISTS documents (
+                    collection TEXT NOT NULL,
+                    document TEXT NOT NULL,
+                    fields TEXT NOT NULL,
+                    PRIMARY KEY (collection, document)
+                )",
+            )
+            .map_err(sqlite_error)?;
+
+        Ok(Self { connection })
+    }
+}
+
+impl Store for Sqlite {
+    fn get(
+        &mut self,
+        collection: &str,
+        document: &str,
+    ) -> Result<Option<Map<String, Value>>, DatabaseError> {
+        let fields = self
+            .connection
+            .query_row(
+                "SELECT fields FROM documents WHERE collection = ?1 AND document = ?2",
+                params![collection, document],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()
+            .map_err(sqlite_error)?;
+
+        match fields {
+            Some(fields) => serde_json::from_str(&fields)
+                .map(Some)
+                .map_err(|error| DatabaseError::Sqlite(error.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    fn commit(
+        &mut self,
+        collection: &str,
+        writes: Vec<(String, Map<String, Value>)>,
+    ) -> Result<(), DatabaseError> {
+        let transaction = self.connection.transaction().map_err(sqlite_error)?;
+
+        for (document, fields) in writes {
+            let stored = transaction
+                .query_row(
+                    "SELECT fields FROM documents WHERE collection = ?1 AND document = ?2",
+                    params![collection, document],
+                    |row| row.get::<_, String>(0),
+                )
+                .optional()
+                .map_err(sqlite_error)?;
+
+            //Fields are merged into the document, like a Firestore update mask
+            let mut merged = match stored {
+                Some(stored) => serde_json::from_str::<Map<String, Value>>(&stored)
+                    .map_err(|error| DatabaseError::Sqlite(error.to_string()))?,
+                None => Map::new(),
+            };
+
+            merged.extend(fields);
+
+            transaction
+                .execute(
+                    "INSERT OR REPLACE INTO documents (collection, document, fields) VALUES (?1, ?2, ?3)",
+                    params![collection, document, Value::Object(merged).to_string()],
+                )
+                .map_err(sqlite_error)?;
+        }
+
+        //Dropping the transaction without committing rolls it back
+        transaction.commit().map_err(sqlite_error)
+    }
+}
+
+fn sqlite_error(error: rusqlite::Error) -> DatabaseError {
+    DatabaseError::Sqlite(error.to_string())
+}
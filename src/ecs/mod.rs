@@ -0,0 +1,87 @@
+pub mod storage;
+
+use procedural_generation::id_types::Ship;
+use std::collections::HashMap;
+use std::sync::{PoisonError, RwLock};
+
+// Maps ships to the dense indices shared by every component storage.
+pub struct Entities {
+    capacity: usize,
+
+    data: RwLock<Indices>,
+}
+
+struct Indices {
+    indices: HashMap<Ship, usize>,
+    references: Vec<usize>,
+    free: Vec<usize>,
+}
+
+impl Entities {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+
+            data: RwLock::new(Indices {
+                indices: HashMap::with_capacity(capacity),
+                references: Vec::with_capacity(capacity),
+                free: Vec::new(),
+            }),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn index(&self, ship_id: &Ship) -> Option<usize> {
+        let data = self.data.read().unwrap_or_else(PoisonError::into_inner);
+
+        data.indices.get(ship_id).copied()
+    }
+
+    // Each storage holding a component of the ship keeps a reference to its index.
+    fn acquire(&self, ship_id: &Ship) -> Option<usize> {
+        let mut data = self.data.write().unwrap_or_else(PoisonError::into_inner);
+
+        if let Some(index) = data.indices.get(ship_id).copied() {
+            data.references[index] += 1;
+
+            return Some(index);
+        }
+
+        let index = match data.free.pop() {
+            Some(index) => index,
+            None if data.references.len() < self.capacity => {
+                data.references.push(0);
+
+                data.references.len() - 1
+            }
+            None => return None,
+        };
+
+        data.references[index] = 1;
+
+        data.indices.insert(*ship_id, index);
+
+        Some(index)
+    }
+
+    //The index is recycled once the last component of the ship is removed
+    fn release(&self, ship_id: &Ship) {
+        let mut data = self.data.write().unwrap_or_else(PoisonError::into_inner);
+
+        let index = match data.indices.get(ship_id).copied() {
+            Some(index) => index,
+            None => return,
+        };
+
+        data.references[index] -= 1;
+
+        if data.references[index] == 0 {
+            data.indices.remove(ship_id);
+
+            data.free.push(index);
+        }
+    }
+}
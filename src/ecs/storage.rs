@@ -0,0 +1,202 @@
+use crate::ecs::Entities;
+use procedural_generation::id_types::Ship;
+use sparseset::SparseSet;
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+// Components of one type, densely packed and indexed by entity.
+pub struct Storage<T> {
+    entities: Arc<Entities>,
+
+    data: RwLock<SparseSet<(Ship, T)>>,
+}
+
+impl<T> Storage<T> {
+    pub fn new(entities: Arc<Entities>) -> Self {
+        let capacity = entities.capacity();
+
+        Self {
+            entities,
+
+            data: RwLock::new(SparseSet::with_capacity(capacity)),
+        }
+    }
+
+    pub fn insert(&self, ship_id: &Ship, component: T) {
+        let mut set = self.write();
+
+        self.insert_into(&mut set, ship_id, component);
+    }
+
+    // All components are inserted under a single lock acquisition.
+    pub fn insert_batch<I>(&self, components: I)
+    where
+        I: IntoIterator<Item = (Ship, T)>,
+    {
+        let mut set = self.write();
+
+        for (ship_id, component) in components {
+            self.insert_into(&mut set, &ship_id, component);
+        }
+    }
+
+    fn insert_into(&self, set: &mut SparseSet<(Ship, T)>, ship_id: &Ship, component: T) {
+        if let Some(index) = self.entities.index(ship_id) {
+            if let Some(entry) = set.get_mut(index) {
+                entry.1 = component;
+                return;
+            }
+        }
+
+        match self.entities.acquire(ship_id) {
+            Some(index) => set.insert(index, (*ship_id, component)),
+            None => println!("Entity capacity reached, component dropped"),
+        }
+    }
+
+    pub fn remove(&self, ship_id: &Ship) -> Option<T> {
+        let mut set = self.write();
+
+        let index = self.entities.index(ship_id)?;
+
+        let (_, component) = set.remove(index)?;
+
+        self.entities.release(ship_id);
+
+        Some(component)
+    }
+
+    pub fn get(&self, ship_id: &Ship) -> Option<T>
+    where
+        T: Clone,
+    {
+        self.with(ship_id, |component| component.clone())
+    }
+
+    pub fn with<F, R>(&self, ship_id: &Ship, f: F) -> Option<R>
+    where
+        F: FnOnce(&T) -> R,
+    {
+        let set = self.read();
+
+        let index = self.entities.index(ship_id)?;
+
+        set.get(index).map(|(_, component)| f(component))
+    }
+
+    // Only this storage is locked while the component is modified.
+    pub fn update<F, R>(&self, ship_id: &Ship, f: F) -> Option<R>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        let mut set = self.write();
+
+        let index = self.entities.index(ship_id)?;
+
+        set.get_mut(index).map(|(_, component)| f(component))
+    }
+
+    pub fn for_each<F>(&self, mut f: F)
+    where
+        F: FnMut(&Ship, &T),
+    {
+        let set = self.read();
+
+        for entry in set.iter() {
+            let (ship_id, component) = &entry.value;
+
+            f(ship_id, component);
+        }
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, SparseSet<(Ship, T)>> {
+        self.data.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, SparseSet<(Ship, T)>> {
+        self.data.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn storages(capacity: usize) -> (Arc<Entities>, Storage<u32>, Storage<&'static str>) {
+        let entities = Arc::new(Entities::new(capacity));
+
+        let numbers = Storage::new(entities.clone());
+        let names = Storage::new(entities.clone());
+
+        (entities, numbers, names)
+    }
+
+    #[test]
+    fn storages_share_the_ship_index() {
+        let (entities, numbers, names) = storages(4);
+        let ship_id = serde_json::from_value::<Ship>(json!(1)).expect("Invalid ship id");
+
+        numbers.insert(&ship_id, 1);
+        names.insert(&ship_id, "one");
+
+        assert_eq!(entities.index(&ship_id), Some(0));
+        assert_eq!(numbers.get(&ship_id), Some(1));
+        assert_eq!(names.get(&ship_id), Some("one"));
+    }
+
+    #[test]
+    fn index_is_kept_until_the_last_component_is_removed() {
+        let (entities, numbers, names) = storages(4);
+        let ship_id = serde_json::from_value::<Ship>(json!(1)).expect("Invalid ship id");
+
+        numbers.insert(&ship_id, 1);
+        names.insert(&ship_id, "one");
+
+        assert_eq!(numbers.remove(&ship_id), Some(1));
+        assert_eq!(numbers.get(&ship_id), None);
+        assert_eq!(names.get(&ship_id), Some("one"));
+        assert_eq!(entities.index(&ship_id), Some(0));
+
+        assert_eq!(names.remove(&ship_id), Some("one"));
+        assert_eq!(entities.index(&ship_id), None);
+    }
+
+    #[test]
+    fn replacing_a_component_keeps_one_reference() {
+        let (entities, numbers, _) = storages(4);
+        let ship_id = serde_json::from_value::<Ship>(json!(1)).expect("Invalid ship id");
+
+        numbers.insert(&ship_id, 1);
+        numbers.insert(&ship_id, 2);
+
+        assert_eq!(numbers.get(&ship_id), Some(2));
+
+        numbers.remove(&ship_id);
+
+        assert_eq!(entities.index(&ship_id), None);
+    }
+
+    #[test]
+    fn released_index_is_reused() {
+        let (entities, numbers, names) = storages(2);
+        let first = serde_json::from_value::<Ship>(json!(1)).expect("Invalid ship id");
+        let second = serde_json::from_value::<Ship>(json!(2)).expect("Invalid ship id");
+        let third = serde_json::from_value::<Ship>(json!(3)).expect("Invalid ship id");
+
+        numbers.insert(&first, 1);
+        names.insert(&second, "two");
+
+        //Full, the component is dropped
+        numbers.insert(&third, 3);
+
+        assert_eq!(numbers.get(&third), None);
+
+        numbers.remove(&first);
+        names.insert(&third, "three");
+
+        assert_eq!(entities.index(&third), Some(0));
+        assert_eq!(names.get(&third), Some("three"));
+        assert_eq!(numbers.get(&third), None);
+        assert_eq!(entities.index(&second), Some(1));
+    }
+}
@@ -1,87 +1,162 @@
-use crate::id_types::Ship;
-use std::collections::HashMap;
-use std::sync::mpsc;
-use std::sync::mpsc::{Receiver, Sender};
-use std::sync::{Arc, RwLock};
-use std::thread;
-use std::thread::JoinHandle;
+use crate::database::SystemMessage as DatabaseMsg;
+use crate::ecs::storage::Storage;
+use crate::ecs::Entities;
+use crate::spatial_os::connexion::{SystemMessage as SpatialOSMsg, UpdateComponent};
+use crate::systems;
+use crate::systems::mailbox::Mailbox;
+use crate::systems::registry::Registry;
+use procedural_generation::id_types::{Module, Ship};
+use std::sync::Arc;
+
+//Experience needed to go from level 0 to 1, each level needs that much more
+const LEVEL_EXPERIENCE: u32 = 100;
+
+const SAMPLER_WEIGHT: u32 = 1;
+const SCANNER_WEIGHT: u32 = 2;
+const SENSOR_WEIGHT: u32 = 3;
 
 pub enum SystemMessage {
     AddComponent(Ship, [u8; 5]),
     UpdateComponent(Ship, [u8; 5]),
     RemoveComponent(Ship),
+
+    LoadExperience(Ship, [u32; 5]),
+    AwardExperience(Ship, Module, Vec<u8>),
 }
 
 pub struct System {
-    channel: Receiver<SystemMessage>,
+    spatial_os: Mailbox<SpatialOSMsg>,
+    database: Mailbox<DatabaseMsg>,
 
     levels: Arc<CraftingLevels>,
 }
 
+impl systems::System for System {
+    type Message = SystemMessage;
+
+    const NAME: &'static str = "crafting";
+
+    fn register(registry: &mut Registry) {
+        let entities = registry.get::<Arc<Entities>>();
+
+        registry.insert(Arc::new(CraftingLevels::init(entities)));
+    }
+
+    fn init(registry: &Registry) -> Self {
+        Self {
+            spatial_os: registry.get::<Mailbox<SpatialOSMsg>>(),
+            database: registry.get::<Mailbox<DatabaseMsg>>(),
+
+            levels: registry.get::<Arc<CraftingLevels>>(),
+        }
+    }
+
+    fn handle_message(&mut self, message: SystemMessage) {
+        match message {
+            SystemMessage::AddComponent(ship_id, data) => self.add_levels(&ship_id, &data),
+            SystemMessage::UpdateComponent(ship_id, data) => self.add_levels(&ship_id, &data),
+            SystemMessage::RemoveComponent(ship_id) => self.levels.remove(&ship_id),
+            SystemMessage::LoadExperience(ship_id, experience) => {
+                self.levels.add_experience(&ship_id, &experience)
+            }
+            SystemMessage::AwardExperience(ship_id, module_id, properties) => {
+                self.award_experience(&ship_id, &module_id, &properties)
+            }
+        }
+    }
+}
+
 impl System {
-    pub fn init(capacity: usize) -> (JoinHandle<()>, Sender<SystemMessage>, Arc<CraftingLevels>) {
-        let (tx, rx) = mpsc::channel();
+    fn add_levels(&mut self, ship_id: &Ship, levels: &[u8; 5]) {
+        self.levels.add(ship_id, levels);
 
-        let mut system = Self {
-            channel: rx,
+        let message = DatabaseMsg::UpdateCraftingLevels(*ship_id, *levels);
 
-            levels: Arc::new(CraftingLevels::init(capacity)),
+        self.database.send(message);
+    }
+
+    //Every property of a crafted module trains the level of the same index
+    fn award_experience(&mut self, ship_id: &Ship, module_id: &Module, properties: &[u8]) {
+        let mut levels = match self.levels.read(ship_id) {
+            Some(levels) => levels,
+            None => return,
         };
 
-        let arc = Arc::clone(&system.levels);
+        let mut experience = self.levels.read_experience(ship_id).unwrap_or([0; 5]);
 
-        let handle = thread::spawn(move || {
-            system.update_loop();
-        });
+        let weight = match module_id {
+            Module::Sampler(_) => SAMPLER_WEIGHT,
+            Module::Scanner(_) => SCANNER_WEIGHT,
+            Module::Sensor(_) => SENSOR_WEIGHT,
+        };
 
-        (handle, tx, arc)
-    }
+        let mut level_up = false;
+
+        for (i, property) in properties.iter().enumerate().take(levels.len()) {
+            experience[i] = experience[i].saturating_add(weight * (*property as u32 + 1));
 
-    fn update_loop(&mut self) {
-        while let Ok(result) = self.channel.recv() {
-            match result {
-                SystemMessage::AddComponent(ship_id, data) => self.levels.add(&ship_id, &data),
-                SystemMessage::UpdateComponent(ship_id, data) => self.levels.add(&ship_id, &data),
-                SystemMessage::RemoveComponent(ship_id) => self.levels.remove(&ship_id),
+            while levels[i] < u8::MAX && experience[i] >= required_experience(levels[i]) {
+                experience[i] -= required_experience(levels[i]);
+                levels[i] += 1;
+
+                level_up = true;
             }
         }
+
+        self.levels.add_experience(ship_id, &experience);
+
+        let message = DatabaseMsg::UpdateCraftingExperience(*ship_id, experience);
+
+        self.database.send(message);
+
+        if !level_up {
+            return;
+        }
+
+        self.add_levels(ship_id, &levels);
+
+        let message =
+            SpatialOSMsg::UpdateComponent(*ship_id, UpdateComponent::CraftingLevels(levels));
+
+        self.spatial_os.send(message);
     }
 }
 
+fn required_experience(level: u8) -> u32 {
+    LEVEL_EXPERIENCE * (level as u32 + 1)
+}
+
 pub struct CraftingLevels {
-    data: RwLock<HashMap<Ship, [u8; 5]>>,
+    data: Storage<[u8; 5]>,
+    experience: Storage<[u32; 5]>,
 }
 
 impl CraftingLevels {
-    fn init(capacity: usize) -> Self {
+    fn init(entities: Arc<Entities>) -> Self {
         Self {
-            data: RwLock::new(HashMap::with_capacity(capacity)),
+            data: Storage::new(Arc::clone(&entities)),
+            experience: Storage::new(entities),
         }
     }
 
     fn add(&self, ship_id: &Ship, data: &[u8; 5]) {
-        if let Ok(mut hash_map) = self.data.write() {
-            hash_map.insert(*ship_id, *data);
-        }
+        self.data.insert(ship_id, *data);
     }
 
-    fn remove(&self, ship_id: &Ship) {
-        if let Ok(mut hash_map) = self.data.write() {
-            hash_map.remove(ship_id);
-        }
+    fn add_experience(&self, ship_id: &Ship, experience: &[u32; 5]) {
+        self.experience.insert(ship_id, *experience);
     }
 
-    pub fn read(&self, ship_id: &Ship) -> Option<[u8; 5]> {
-        let data = self.data.read();
+    fn remove(&self, ship_id: &Ship) {
+        self.data.remove(ship_id);
+        self.experience.remove(ship_id);
+    }
 
-        let data = match data {
-            Ok(data) => data,
-            Err(_) => return None,
-        };
+    fn read_experience(&self, ship_id: &Ship) -> Option<[u32; 5]> {
+        self.experience.get(ship_id)
+    }
 
-        match data.get(ship_id) {
-            Some(data) => Some(*data),
-            None => None,
-        }
+    pub fn read(&self, ship_id: &Ship) -> Option<[u8; 5]> {
+        self.data.get(ship_id)
     }
 }
@@ -0,0 +1,358 @@
+use crate::inventory::Inventory;
+use serde_json::{json, Value};
+use std::convert::TryInto;
+use std::fmt;
+
+// Version of the inventory schema, bumped when a field is added to modules or resources.
+pub const VERSION: u16 = 3;
+
+//Each function upgrades a JSON inventory from the version at its index to the next one
+const MIGRATIONS: [fn(Value) -> Value; 3] = [
+    //Inventories were first stored without a version, with the same fields
+    |inventory| inventory,
+    add_crafted_quantities,
+    pairs_to_entries,
+];
+
+//Modules now keep the quantities they were crafted with, the current ones are the best guess
+fn add_crafted_quantities(mut inventory: Value) -> Value {
+    let modules = inventory
+        .pointer_mut("/modules/modules")
+        .and_then(Value::as_array_mut);
+
+    for pair in modules.into_iter().flatten() {
+        if let Some(resources) = pair
+            .pointer_mut("/1/resources")
+            .and_then(Value::as_object_mut)
+        {
+            let quantities = resources.get("quantities").cloned().unwrap_or_default();
+
+            resources.insert("crafted".to_string(), quantities);
+        }
+    }
+
+    inventory
+}
+
+//Maps were lists of [id, value] pairs, Firestore refuses an array directly inside another array
+fn pairs_to_entries(mut inventory: Value) -> Value {
+    for pointer in ["/modules/modules", "/resources/resources"].iter() {
+        let pairs = inventory.pointer_mut(pointer).and_then(Value::as_array_mut);
+
+        for pair in pairs.into_iter().flatten() {
+            if let Value::Array(fields) = pair {
+                let value = fields.pop();
+                let id = fields.pop();
+
+                *pair = json!({ "id": id, "value": value });
+            }
+        }
+    }
+
+    inventory
+}
+
+pub enum EncodingError {
+    Binary(String),
+    Json(String),
+    UnknownVersion(u16),
+}
+
+impl fmt::Display for EncodingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EncodingError::Binary(message) => write!(f, "invalid binary inventory: {}", message),
+            EncodingError::Json(message) => write!(f, "invalid JSON inventory: {}", message),
+            EncodingError::UnknownVersion(version) => {
+                write!(
+                    f,
+                    "unknown inventory version {}, current is {}",
+                    version, VERSION
+                )
+            }
+        }
+    }
+}
+
+//The version followed by the bincode encoded inventory
+pub fn to_bytes(inventory: &Inventory) -> Vec<u8> {
+    let mut bytes = VERSION.to_le_bytes().to_vec();
+
+    bincode::serialize_into(&mut bytes, inventory).expect("Inventory serialization failed");
+
+    bytes
+}
+
+//Binary inventories can't be migrated field by field, older ones are decoded with the struct of their version then migrated as JSON
+pub fn from_bytes(bytes: &[u8]) -> Result<Inventory, EncodingError> {
+    if bytes.len() < 2 {
+        return Err(EncodingError::Binary("missing version".to_string()));
+    }
+
+    let version = u16::from_le_bytes(bytes[..2].try_into().expect("Two bytes"));
+
+    match version {
+        //Version 3 only changed how maps are written in JSON, bincode writes entries like pairs
+        2 | VERSION => bincode::deserialize(&bytes[2..]).map_err(binary_error),
+        1 => {
+            let inventory =
+                bincode::deserialize::<v1::Inventory>(&bytes[2..]).map_err(binary_error)?;
+
+            let inventory = serde_json::to_value(inventory)
+                .map_err(|error| EncodingError::Json(error.to_string()))?;
+
+            migrate(inventory, 1)
+        }
+        _ => Err(EncodingError::UnknownVersion(version)),
+    }
+}
+
+fn binary_error(error: bincode::Error) -> EncodingError {
+    EncodingError::Binary(error.to_string())
+}
+
+pub fn to_json(inventory: &Inventory) -> Value {
+    let inventory = serde_json::to_value(inventory).expect("Inventory serialization failed");
+
+    json!({ "version": VERSION, "inventory": inventory })
+}
+
+pub fn from_json(mut value: Value) -> Result<Inventory, EncodingError> {
+    let (version, inventory) = match value.get("version").and_then(Value::as_u64) {
+        Some(version) => match value.get_mut("inventory").map(Value::take) {
+            Some(inventory) => (version, inventory),
+            None => return Err(EncodingError::Json("missing inventory".to_string())),
+        },
+        None => (0, value),
+    };
+
+    if version > VERSION as u64 {
+        return Err(EncodingError::UnknownVersion(version as u16));
+    }
+
+    migrate(inventory, version)
+}
+
+fn migrate(mut inventory: Value, version: u64) -> Result<Inventory, EncodingError> {
+    for migration in MIGRATIONS.iter().skip(version as usize) {
+        inventory = migration(inventory);
+    }
+
+    serde_json::from_value(inventory).map_err(|error| EncodingError::Json(error.to_string()))
+}
+
+//Inventory as encoded at version 1, before modules kept their crafted quantities
+mod v1 {
+    //Maps were written as [id, value] pairs
+    mod pairs {
+        use serde::{Deserialize, Deserializer, Serialize, Serializer};
+        use std::collections::HashMap;
+        use std::hash::Hash;
+
+        pub fn serialize<K, V, S>(map: &HashMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            K: Serialize,
+            V: Serialize,
+            S: Serializer,
+        {
+            serializer.collect_seq(map.iter())
+        }
+
+        pub fn deserialize<'de, K, V, D>(deserializer: D) -> Result<HashMap<K, V>, D::Error>
+        where
+            K: Deserialize<'de> + Eq + Hash,
+            V: Deserialize<'de>,
+            D: Deserializer<'de>,
+        {
+            let pairs = Vec::<(K, V)>::deserialize(deserializer)?;
+
+            Ok(pairs.into_iter().collect())
+        }
+    }
+
+    use procedural_generation::id_types::{Module, Resource, User};
+    use serde::{Deserialize, Serialize};
+    use std::collections::HashMap;
+    use std::num::NonZeroU32;
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Inventory {
+        pub modules: Modules,
+        pub resources: Resources,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Modules {
+        #[serde(with = "pairs")]
+        pub modules: HashMap<Module, ModuleStats>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct ModuleStats {
+        pub name: String,
+        pub creator: User,
+        pub properties: Vec<u8>,
+        pub resources: ModuleResources,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct ModuleResources {
+        pub resource_ids: Vec<Resource>,
+        pub quantities: Vec<NonZeroU32>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Resources {
+        #[serde(with = "pairs")]
+        pub resources: HashMap<Resource, NonZeroU32>,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inventory::modules::{ModuleResources, ModuleStats};
+    use procedural_generation::id_types::{Module, Resource, User};
+    use procedural_generation::modules::samplers::SamplerStats;
+    use std::num::NonZeroU32;
+
+    fn inventory() -> Inventory {
+        let user_id = serde_json::from_value::<User>(json!(1)).expect("Invalid user id");
+        let module_id =
+            serde_json::from_value::<Module>(json!({ "Sampler": 1 })).expect("Invalid module id");
+
+        let requirements = match SamplerStats::from_properties(&[0; 5]) {
+            Ok(stats) => stats.get_requirements(),
+            Err(_) => panic!("Invalid sampler properties"),
+        };
+        let (resources, quantities): (Vec<Resource>, Vec<NonZeroU32>) =
+            requirements.into_iter().unzip();
+
+        let module_res = ModuleResources::new(&resources, &quantities);
+        let module = ModuleStats::new("Sampler".to_string(), user_id, &[0; 5], module_res);
+
+        let mut inventory = Inventory::new(8);
+
+        inventory.modules.add(&module_id, module);
+        inventory.resources.update_or_insert(&resources[0], 5);
+
+        inventory
+    }
+
+    fn value(inventory: &Inventory) -> Value {
+        serde_json::to_value(inventory).expect("Inventory serialization failed")
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let inventory = inventory();
+
+        let decoded = from_bytes(&to_bytes(&inventory)).unwrap_or_else(|error| panic!("{}", error));
+
+        assert_eq!(value(&decoded), value(&inventory));
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let inventory = inventory();
+
+        let decoded = from_json(to_json(&inventory)).unwrap_or_else(|error| panic!("{}", error));
+
+        assert_eq!(value(&decoded), value(&inventory));
+    }
+
+    //What version 1 wrote for a sampler worn by one unit on each resource
+    fn v1_fixture() -> Vec<u8> {
+        let user_id = serde_json::from_value::<User>(json!(1)).expect("Invalid user id");
+        let module_id =
+            serde_json::from_value::<Module>(json!({ "Sampler": 1 })).expect("Invalid module id");
+
+        let requirements = match SamplerStats::from_properties(&[0; 5]) {
+            Ok(stats) => stats.get_requirements(),
+            Err(_) => panic!("Invalid sampler properties"),
+        };
+        let (resources, quantities): (Vec<Resource>, Vec<NonZeroU32>) =
+            requirements.into_iter().unzip();
+
+        let worn = quantities
+            .iter()
+            .map(|quantity| NonZeroU32::new(quantity.get() - 1).expect("Quantity above one"))
+            .collect();
+
+        let module = v1::ModuleStats {
+            name: "Sampler".to_string(),
+            creator: user_id,
+            properties: vec![0; 5],
+            resources: v1::ModuleResources {
+                resource_ids: resources,
+                quantities: worn,
+            },
+        };
+
+        let inventory = v1::Inventory {
+            modules: v1::Modules {
+                modules: vec![(module_id, module)].into_iter().collect(),
+            },
+            resources: v1::Resources {
+                resources: Default::default(),
+            },
+        };
+
+        let mut bytes = 1u16.to_le_bytes().to_vec();
+
+        bincode::serialize_into(&mut bytes, &inventory).expect("Inventory serialization failed");
+
+        bytes
+    }
+
+    #[test]
+    fn v1_binary_is_migrated() {
+        let module_id =
+            serde_json::from_value::<Module>(json!({ "Sampler": 1 })).expect("Invalid module id");
+
+        let inventory = from_bytes(&v1_fixture()).unwrap_or_else(|error| panic!("{}", error));
+
+        //The quantities it had are all it can be repaired to
+        let wear = inventory.modules.wear(&module_id).expect("Module decoded");
+
+        assert!(wear.iter().all(|(_, missing)| *missing == 0));
+    }
+
+    #[test]
+    fn v2_json_pairs_are_migrated() {
+        let inventory = inventory();
+        let mut encoded = to_json(&inventory);
+
+        //Entries written back as the [id, value] pairs of version 2
+        for pointer in [
+            "/inventory/modules/modules",
+            "/inventory/resources/resources",
+        ]
+        .iter()
+        {
+            let entries = encoded.pointer_mut(pointer).and_then(Value::as_array_mut);
+
+            for entry in entries.into_iter().flatten() {
+                *entry = json!([entry["id"].take(), entry["value"].take()]);
+            }
+        }
+
+        encoded["version"] = json!(2);
+
+        let decoded = from_json(encoded).unwrap_or_else(|error| panic!("{}", error));
+
+        assert_eq!(value(&decoded), value(&inventory));
+    }
+
+    #[test]
+    fn unknown_binary_version_is_rejected() {
+        let mut bytes = to_bytes(&inventory());
+
+        bytes[..2].copy_from_slice(&(VERSION + 1).to_le_bytes());
+
+        assert!(matches!(
+            from_bytes(&bytes),
+            Err(EncodingError::UnknownVersion(version)) if version == VERSION + 1
+        ));
+    }
+}
@@ -1,123 +1,224 @@
+pub mod crafting;
+pub mod encoding;
 mod modules;
+mod pairs;
+mod queue;
 mod resources;
 
+use crate::database::SystemMessage as DatabaseMsg;
+use crate::ecs::storage::Storage;
+use crate::ecs::Entities;
+use crate::inventory::crafting::{CraftingLevels, SystemMessage as CraftingMsg};
 use crate::inventory::modules::{ModuleResources, ModuleStats, Modules};
+use crate::inventory::queue::{CraftJob, CraftingQueues, Progress};
 use crate::inventory::resources::Resources;
 use crate::ships::identifications::Identifiers;
-use crate::spatial_os::connexion::{SystemMessage as SpatialOSMsg, UpdateComponent};
-use procedural_generation::id_types::{Module, Resource, Ship, User};
+use crate::spatial_os::connexion::{
+    ClientResponse, SystemMessage as SpatialOSMsg, UpdateComponent,
+};
+use crate::systems;
+use crate::systems::mailbox::Mailbox;
+use crate::systems::registry::Registry;
+use procedural_generation::id_types::{Module, Resource, Ship};
 use procedural_generation::modules::samplers::SamplerStats;
 use procedural_generation::modules::scanners::ScannerStats;
 use procedural_generation::modules::sensors::SensorStats;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::mem;
 use std::num::NonZeroU32;
-use std::sync::mpsc;
-use std::sync::mpsc::{Receiver, Sender};
 use std::sync::Arc;
-use std::sync::RwLock;
-use std::thread;
-use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+const QUEUE_RESOLUTION: Duration = Duration::from_millis(250);
 
 pub enum SystemMessage {
-    AddOrUpdateComponent(Ship, Inventory),
+    AddComponent(Ship, Inventory),
+    UpdateComponent(Ship, Inventory),
     RemoveComponent(Ship),
     UpdateModuleDurability(Ship, Module, i32),
     AddOrUpdateResource(Ship, Resource, i32),
     CraftModule(Ship, Module, String, Vec<u8>, Vec<Resource>),
+    CancelCraft(Ship, Module),
+    RepairModule(Ship, Module),
+}
+
+pub enum CraftError {
+    NoInventory,
+    NoCreator,
+    NoCraftingLevels,
+    AboveCraftingLevel,
+    InvalidProperties,
+    WrongResourceCount,
+    WrongResourceKind,
+    NotEnoughResources,
+    AlreadyQueued,
+    AlreadyOwned,
+    NotQueued,
+}
+
+impl fmt::Display for CraftError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CraftError::NoInventory => write!(f, "ship has no inventory"),
+            CraftError::NoCreator => write!(f, "ship has no owner"),
+            CraftError::NoCraftingLevels => write!(f, "ship has no crafting levels"),
+            CraftError::AboveCraftingLevel => write!(f, "properties above crafting levels"),
+            CraftError::InvalidProperties => write!(f, "invalid module properties"),
+            CraftError::WrongResourceCount => write!(f, "wrong number of resources"),
+            CraftError::WrongResourceKind => write!(f, "wrong kind of resource"),
+            CraftError::NotEnoughResources => write!(f, "not enough resources"),
+            CraftError::AlreadyQueued => write!(f, "module already queued"),
+            CraftError::AlreadyOwned => write!(f, "module already in the inventory"),
+            CraftError::NotQueued => write!(f, "module not queued"),
+        }
+    }
+}
+
+pub enum RepairError {
+    NoInventory,
+    NoModule,
+    NotDamaged,
+    NotEnoughResources,
+}
+
+impl fmt::Display for RepairError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RepairError::NoInventory => write!(f, "ship has no inventory"),
+            RepairError::NoModule => write!(f, "module not in inventory"),
+            RepairError::NotDamaged => write!(f, "module not damaged"),
+            RepairError::NotEnoughResources => write!(f, "not enough resources"),
+        }
+    }
 }
 
 pub struct System {
-    channel: Receiver<SystemMessage>,
-    spatial_os: Sender<SpatialOSMsg>,
+    spatial_os: Mailbox<SpatialOSMsg>,
+    database: Mailbox<DatabaseMsg>,
+    crafting: Mailbox<CraftingMsg>,
 
     inventories: Arc<Inventories>,
+    queues: Arc<CraftingQueues>,
 
     identifiers: Arc<Identifiers>,
+    crafting_levels: Arc<CraftingLevels>,
 }
 
-impl System {
-    pub fn init(
-        capacity: usize,
-        spatial_os: Sender<SpatialOSMsg>,
-        identifiers: Arc<Identifiers>,
-    ) -> (JoinHandle<()>, Sender<SystemMessage>, Arc<Inventories>) {
-        let (tx, channel) = mpsc::channel();
+impl systems::System for System {
+    type Message = SystemMessage;
 
-        let mut system = Self {
-            channel,
-            spatial_os,
+    const NAME: &'static str = "inventory";
 
-            inventories: Arc::new(Inventories::new(capacity)),
+    fn register(registry: &mut Registry) {
+        let entities = registry.get::<Arc<Entities>>();
 
-            identifiers,
-        };
+        registry.insert(Arc::new(Inventories::new(entities.clone())));
+        registry.insert(Arc::new(CraftingQueues::new(entities)));
+    }
 
-        let arc = Arc::clone(&system.inventories);
+    fn init(registry: &Registry) -> Self {
+        Self {
+            spatial_os: registry.get::<Mailbox<SpatialOSMsg>>(),
+            database: registry.get::<Mailbox<DatabaseMsg>>(),
+            crafting: registry.get::<Mailbox<CraftingMsg>>(),
 
-        let handle = thread::spawn(move || {
-            system.update_loop();
-        });
+            inventories: registry.get::<Arc<Inventories>>(),
+            queues: registry.get::<Arc<CraftingQueues>>(),
 
-        (handle, tx, arc)
+            identifiers: registry.get::<Arc<Identifiers>>(),
+            crafting_levels: registry.get::<Arc<CraftingLevels>>(),
+        }
     }
 
-    fn update_loop(&mut self) {
-        while let Ok(message) = self.channel.recv() {
-            match message {
-                SystemMessage::AddOrUpdateComponent(ship_id, inventory) => {
-                    self.inventories.add(&ship_id, inventory)
-                }
-                SystemMessage::RemoveComponent(ship_id) => self.inventories.remove(&ship_id),
-                SystemMessage::UpdateModuleDurability(ship_id, module_id, delta) => {
-                    self.update_module_durability(&ship_id, &module_id, delta)
-                }
-                SystemMessage::AddOrUpdateResource(ship_id, resource_id, quantity) => {
-                    self.add_or_update_resource(&ship_id, &resource_id, quantity)
-                }
-                SystemMessage::CraftModule(ship_id, module_id, name, craft_levels, resources) => {
-                    self.craft_module(&ship_id, &module_id, name, &craft_levels, &resources)
-                }
+    fn handle_message(&mut self, message: SystemMessage) {
+        match message {
+            SystemMessage::AddComponent(ship_id, inventory) => {
+                self.add_inventory(&ship_id, inventory)
+            }
+            SystemMessage::UpdateComponent(ship_id, inventory) => {
+                self.save(&ship_id, inventory.clone());
+
+                self.inventories.add(&ship_id, inventory)
+            }
+            SystemMessage::RemoveComponent(ship_id) => self.remove_inventory(&ship_id),
+            SystemMessage::UpdateModuleDurability(ship_id, module_id, delta) => {
+                self.update_module_durability(&ship_id, &module_id, delta)
+            }
+            SystemMessage::AddOrUpdateResource(ship_id, resource_id, quantity) => {
+                self.add_or_update_resource(&ship_id, &resource_id, quantity)
+            }
+            SystemMessage::CraftModule(ship_id, module_id, name, craft_levels, resources) => {
+                self.craft_module(&ship_id, &module_id, name, &craft_levels, &resources)
+            }
+            SystemMessage::CancelCraft(ship_id, module_id) => {
+                self.cancel_craft(&ship_id, &module_id)
+            }
+            SystemMessage::RepairModule(ship_id, module_id) => {
+                self.repair_module(&ship_id, &module_id)
             }
         }
     }
 
-    fn update_module_durability(&mut self, ship_id: &Ship, module_id: &Module, delta: i32) {
-        let mut inv = self.inventories.data.write().expect("Lock poisoned");
+    fn frame_time(&self) -> Option<Duration> {
+        Some(QUEUE_RESOLUTION)
+    }
 
-        let inv = inv.get_mut(&ship_id);
-        let inv = match inv {
-            Some(inv) => inv,
-            None => return,
-        };
+    fn tick(&mut self) {
+        self.advance_queues();
+    }
+
+    fn shutdown(&mut self) {
+        //Unfinished crafts are not persisted, restarting cancels them and gives their resources back
+        for ship_id in self.queues.ships() {
+            self.refund_queue(&ship_id);
+        }
+    }
+}
 
-        inv.modules.update_module_durability(module_id, delta);
+impl System {
+    //Loaded inventories are in their stored form, the queue of a ship that outlived
+    //a restart still holds its reserved resources
+    fn add_inventory(&mut self, ship_id: &Ship, mut inventory: Inventory) {
+        for (resource_id, quantity) in self.queues.reserved(ship_id) {
+            inventory
+                .resources
+                .update_or_insert(&resource_id, -(quantity.get() as i32));
+        }
 
-        let message =
-            SpatialOSMsg::UpdateComponent(*ship_id, UpdateComponent::Inventory(inv.clone()));
+        self.save(ship_id, inventory.clone());
 
-        self.spatial_os
-            .send(message)
-            .expect("SpatialOS connexion terminated");
+        self.inventories.add(ship_id, inventory)
     }
 
-    fn add_or_update_resource(&mut self, ship_id: &Ship, resource_id: &Resource, quantity: i32) {
-        let mut inv = self.inventories.data.write().expect("Lock poisoned");
+    fn remove_inventory(&mut self, ship_id: &Ship) {
+        self.refund_queue(ship_id);
+
+        self.inventories.remove(ship_id);
+    }
 
-        let inv = inv.get_mut(&ship_id);
+    fn update_module_durability(&mut self, ship_id: &Ship, module_id: &Module, delta: i32) {
+        let inv = self.inventories.update(ship_id, |inv| {
+            inv.modules.update_module_durability(module_id, delta)
+        });
         let inv = match inv {
             Some(inv) => inv,
             None => return,
         };
 
-        inv.resources.update_or_insert(&resource_id, quantity);
+        self.send_inventory(ship_id, inv);
+    }
 
-        let message =
-            SpatialOSMsg::UpdateComponent(*ship_id, UpdateComponent::Inventory(inv.clone()));
+    fn add_or_update_resource(&mut self, ship_id: &Ship, resource_id: &Resource, quantity: i32) {
+        let inv = self.inventories.update(ship_id, |inv| {
+            inv.resources.update_or_insert(&resource_id, quantity)
+        });
+        let inv = match inv {
+            Some(inv) => inv,
+            None => return,
+        };
 
-        self.spatial_os
-            .send(message)
-            .expect("SpatialOS connexion terminated");
+        self.send_inventory(ship_id, inv);
     }
 
     fn craft_module(
@@ -128,46 +229,62 @@ impl System {
         craft_levels: &[u8],
         resources: &[Resource],
     ) {
-        let mut inv = self.inventories.data.write().expect("Lock poisoned");
+        let result = self
+            .try_craft_module(ship_id, module_id, name, craft_levels, resources)
+            .map(|_| *module_id);
 
-        let inv = inv.get_mut(ship_id);
-        let inv = match inv {
-            Some(inv) => inv,
-            None => return,
-        };
+        let message = SpatialOSMsg::CommandResponse(*ship_id, ClientResponse::CraftModule(result));
 
-        let creator = self.identifiers.read(ship_id);
-        let creator = match creator {
-            Some(creator) => creator,
-            None => return,
-        };
+        self.spatial_os.send(message);
+    }
+
+    fn try_craft_module(
+        &mut self,
+        ship_id: &Ship,
+        module_id: &Module,
+        name: String,
+        craft_levels: &[u8],
+        resources: &[Resource],
+    ) -> Result<(), CraftError> {
+        let mut inv = self
+            .inventories
+            .read(ship_id)
+            .ok_or(CraftError::NoInventory)?;
+
+        let creator = self
+            .identifiers
+            .read(ship_id)
+            .ok_or(CraftError::NoCreator)?;
+
+        let levels = self
+            .crafting_levels
+            .read(ship_id)
+            .ok_or(CraftError::NoCraftingLevels)?;
+
+        //A property can't be above the crafting level of the same index
+        if craft_levels.len() > levels.len()
+            || craft_levels
+                .iter()
+                .zip(levels.iter())
+                .any(|(property, level)| property > level)
+        {
+            return Err(CraftError::AboveCraftingLevel);
+        }
 
         let requirements = match module_id {
-            Module::Sampler(_) => {
-                if let Ok(stats) = SamplerStats::from_properties(craft_levels) {
-                    stats.get_requirements()
-                } else {
-                    return;
-                }
-            }
-            Module::Scanner(_) => {
-                if let Ok(stats) = ScannerStats::from_properties(craft_levels) {
-                    stats.get_requirements()
-                } else {
-                    return;
-                }
-            }
-            Module::Sensor(_) => {
-                if let Ok(stats) = SensorStats::from_properties(craft_levels) {
-                    stats.get_requirements()
-                } else {
-                    return;
-                }
-            }
+            Module::Sampler(_) => SamplerStats::from_properties(craft_levels)
+                .map(|stats| stats.get_requirements())
+                .map_err(|_| CraftError::InvalidProperties)?,
+            Module::Scanner(_) => ScannerStats::from_properties(craft_levels)
+                .map(|stats| stats.get_requirements())
+                .map_err(|_| CraftError::InvalidProperties)?,
+            Module::Sensor(_) => SensorStats::from_properties(craft_levels)
+                .map(|stats| stats.get_requirements())
+                .map_err(|_| CraftError::InvalidProperties)?,
         };
 
         if requirements.len() != resources.len() {
-            return;
+            return Err(CraftError::WrongResourceCount);
         }
 
         for (i, requirement) in requirements.iter().enumerate() {
@@ -175,107 +292,373 @@ impl System {
 
             //discriminant is used to compare enum variants, disregarding struct values
             if mem::discriminant(req_resource) != mem::discriminant(&resources[i]) {
-                return;
+                return Err(CraftError::WrongResourceKind);
             }
 
             if !inv.resources.has_enough(&resources[i], *req_quantity) {
-                return;
+                return Err(CraftError::NotEnoughResources);
             }
         }
 
+        if self.queues.contains(ship_id, module_id) {
+            return Err(CraftError::AlreadyQueued);
+        }
+
+        //The finished module would replace the one with the same ID
+        if inv.modules.contains(module_id) {
+            return Err(CraftError::AlreadyOwned);
+        }
+
         let quantities = requirements
             .into_iter()
             .map(|tuple| tuple.1)
             .collect::<Vec<NonZeroU32>>();
 
-        inv.craft_new_module(
-            module_id,
+        //Resources are reserved until the module is done or cancelled
+        inv.take_resources(resources, &quantities);
+
+        let job = CraftJob::new(
+            *module_id,
             name,
-            &creator,
-            craft_levels,
-            resources,
-            &quantities,
+            creator,
+            craft_levels.to_vec(),
+            resources.to_vec(),
+            quantities,
         );
 
-        let message =
-            SpatialOSMsg::UpdateComponent(*ship_id, UpdateComponent::Inventory(inv.clone()));
+        self.queues.push(ship_id, job);
+
+        self.inventories.add(ship_id, inv.clone());
+
+        self.send_inventory(ship_id, inv);
+
+        Ok(())
+    }
+
+    fn cancel_craft(&mut self, ship_id: &Ship, module_id: &Module) {
+        let result = match self.queues.cancel(ship_id, module_id) {
+            Some(job) => {
+                self.refund(ship_id, &[job]);
+
+                Ok(*module_id)
+            }
+            None => Err(CraftError::NotQueued),
+        };
+
+        let message = SpatialOSMsg::CommandResponse(*ship_id, ClientResponse::CancelCraft(result));
+
+        self.spatial_os.send(message);
+    }
+
+    fn advance_queues(&mut self) {
+        let now = Instant::now();
+
+        for ship_id in self.queues.ships() {
+            let response = match self.queues.advance(&ship_id, now) {
+                Progress::Unchanged => continue,
+                Progress::Progressed(module_id, progress) => {
+                    ClientResponse::CraftProgress(module_id, progress)
+                }
+                Progress::Completed(job) => match self.complete_craft(&ship_id, job) {
+                    Some(module_id) => ClientResponse::CraftCompleted(module_id),
+                    None => continue,
+                },
+            };
+
+            let message = SpatialOSMsg::CommandResponse(ship_id, response);
+
+            self.spatial_os.send(message);
+        }
+    }
+
+    fn complete_craft(&mut self, ship_id: &Ship, job: CraftJob) -> Option<Module> {
+        let module_id = job.module_id;
+        let properties = job.properties.clone();
+
+        let inv = self
+            .inventories
+            .update(ship_id, |inv| inv.add_module(job))?;
+
+        self.send_inventory(ship_id, inv);
+
+        let message = CraftingMsg::AwardExperience(*ship_id, module_id, properties);
+
+        self.crafting.send(message);
+
+        Some(module_id)
+    }
+
+    fn refund_queue(&mut self, ship_id: &Ship) {
+        let jobs = self.queues.remove(ship_id);
+
+        if !jobs.is_empty() {
+            self.refund(ship_id, &jobs);
+        }
+    }
+
+    fn refund(&mut self, ship_id: &Ship, jobs: &[CraftJob]) {
+        let inv = self.inventories.update(ship_id, |inv| {
+            for job in jobs {
+                inv.give_resources(&job.resources, &job.quantities);
+            }
+        });
+
+        if let Some(inv) = inv {
+            self.send_inventory(ship_id, inv);
+        }
+    }
+
+    fn repair_module(&mut self, ship_id: &Ship, module_id: &Module) {
+        let result = self
+            .try_repair_module(ship_id, module_id)
+            .map(|_| *module_id);
+
+        let message = SpatialOSMsg::CommandResponse(*ship_id, ClientResponse::RepairModule(result));
 
-        self.spatial_os
-            .send(message)
-            .expect("SpatialOS connexion terminated");
+        self.spatial_os.send(message);
+    }
+
+    fn try_repair_module(&mut self, ship_id: &Ship, module_id: &Module) -> Result<(), RepairError> {
+        let mut inv = self
+            .inventories
+            .read(ship_id)
+            .ok_or(RepairError::NoInventory)?;
+
+        inv.repair_module(module_id)?;
+
+        self.inventories.add(ship_id, inv.clone());
+
+        self.send_inventory(ship_id, inv);
+
+        Ok(())
+    }
+
+    fn send_inventory(&self, ship_id: &Ship, inventory: Inventory) {
+        let bytes = encoding::to_bytes(&inventory);
+
+        self.save(ship_id, inventory);
+
+        let message = SpatialOSMsg::UpdateComponent(*ship_id, UpdateComponent::Inventory(bytes));
+
+        self.spatial_os.send(message);
+    }
+
+    //The stored inventory still holds the resources reserved by the queue,
+    //so crafts lost in a crash are refunded like the ones cancelled on shutdown
+    fn save(&self, ship_id: &Ship, mut inventory: Inventory) {
+        for (resource_id, quantity) in self.queues.reserved(ship_id) {
+            inventory
+                .resources
+                .update_or_insert(&resource_id, quantity.get() as i32);
+        }
+
+        let message = DatabaseMsg::UpdateInventory(*ship_id, inventory);
+
+        self.database.send(message);
     }
 }
 
 pub struct Inventories {
-    data: RwLock<HashMap<Ship, Inventory>>,
+    data: Storage<Inventory>,
 }
 
 impl Inventories {
-    fn new(capacity: usize) -> Self {
+    fn new(entities: Arc<Entities>) -> Self {
         Self {
-            data: RwLock::new(HashMap::with_capacity(capacity)),
+            data: Storage::new(entities),
         }
     }
 
     fn add(&self, ship_id: &Ship, inventory: Inventory) {
-        if let Ok(mut hash_map) = self.data.write() {
-            hash_map.insert(*ship_id, inventory);
-        }
+        self.data.insert(ship_id, inventory);
     }
 
     fn remove(&self, ship_id: &Ship) {
-        if let Ok(mut hash_map) = self.data.write() {
-            hash_map.remove(ship_id);
-        }
+        self.data.remove(ship_id);
     }
 
-    pub fn get_module_properties(&self, ship_id: &Ship, module_id: &Module) -> Option<Vec<u8>> {
-        let hash_map = self.data.read().expect("Lock poisoned");
+    //Returns the updated inventory
+    fn update<F>(&self, ship_id: &Ship, f: F) -> Option<Inventory>
+    where
+        F: FnOnce(&mut Inventory),
+    {
+        self.data.update(ship_id, |inv| {
+            f(inv);
 
-        let inv = match hash_map.get(ship_id) {
-            Some(inv) => inv,
-            None => return None,
-        };
+            inv.clone()
+        })
+    }
 
-        match inv.modules.modules.get(module_id) {
-            Some(module) => Some(module.get_properties()),
-            None => None,
-        }
+    fn read(&self, ship_id: &Ship) -> Option<Inventory> {
+        self.data.get(ship_id)
+    }
+
+    pub fn get_module_properties(&self, ship_id: &Ship, module_id: &Module) -> Option<Vec<u8>> {
+        self.data
+            .with(ship_id, |inv| {
+                inv.modules
+                    .modules
+                    .get(module_id)
+                    .map(|module| module.get_properties())
+            })
+            .flatten()
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Inventory /*Placeholder Component*/ {
     modules: Modules,
     resources: Resources,
 }
 
 impl Inventory {
-    fn new(capacity: usize) -> Self {
+    pub fn new(capacity: usize) -> Self {
         Self {
             modules: Modules::with_capacity(capacity),
             resources: Resources::with_capacity(capacity),
         }
     }
 
-    fn craft_new_module(
-        &mut self,
-        module_id: &Module,
-        name: String,
-        creator: &User,
-        properties: &[u8],
-        resources: &[Resource],
-        quantities: &[NonZeroU32],
-    ) {
-        let module_res = ModuleResources::new(resources, quantities);
+    fn add_module(&mut self, job: CraftJob) {
+        let module_res = ModuleResources::new(&job.resources, &job.quantities);
+
+        let module = ModuleStats::new(job.name, job.creator, &job.properties, module_res);
+
+        self.modules.add(&job.module_id, module);
+    }
+
+    fn repair_module(&mut self, module_id: &Module) -> Result<(), RepairError> {
+        let wear = self.modules.wear(module_id).ok_or(RepairError::NoModule)?;
+
+        //Every resource gets back the same share of what it lost, the scarcest one sets the share
+        let (available, missing) = wear
+            .iter()
+            .filter(|(_, missing)| *missing > 0)
+            .map(|(resource_id, missing)| {
+                (self.resources.quantity(resource_id).min(*missing), *missing)
+            })
+            .min_by(|(a_available, a_missing), (b_available, b_missing)| {
+                (*a_available as u64 * *b_missing as u64)
+                    .cmp(&(*b_available as u64 * *a_missing as u64))
+            })
+            .ok_or(RepairError::NotDamaged)?;
+
+        if available == 0 {
+            return Err(RepairError::NotEnoughResources);
+        }
 
-        let module = ModuleStats::new(name, *creator, properties, module_res);
+        let amounts = wear
+            .iter()
+            .map(|(_, lost)| (*lost as u64 * available as u64 / missing as u64) as u32)
+            .collect::<Vec<u32>>();
 
-        self.modules.add(module_id, module);
+        for ((resource_id, _), amount) in wear.iter().zip(amounts.iter()) {
+            self.resources
+                .update_or_insert(resource_id, -(*amount as i32));
+        }
+
+        self.modules.repair(module_id, &amounts);
+
+        Ok(())
+    }
+
+    fn take_resources(&mut self, resources: &[Resource], quantities: &[NonZeroU32]) {
+        for (resource, quantity) in resources.iter().zip(quantities.iter()) {
+            self.resources
+                .update_or_insert(resource, -(quantity.get() as i32));
+        }
+    }
 
-        for (i, resource) in resources.iter().enumerate() {
+    fn give_resources(&mut self, resources: &[Resource], quantities: &[NonZeroU32]) {
+        for (resource, quantity) in resources.iter().zip(quantities.iter()) {
             self.resources
-                .update_or_insert(resource, -(quantities[i].get() as i32));
+                .update_or_insert(resource, quantity.get() as i32);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ships::identifications::{self, SystemMessage as IdMsg};
+    use crate::systems::mailbox::{Envelope, Overflow};
+    use crate::systems::System as _;
+    use procedural_generation::id_types::User;
+    use serde_json::json;
+
+    #[test]
+    fn resync_after_a_restart_keeps_the_reserved_resources() {
+        let ship_id = serde_json::from_value::<Ship>(json!(1)).expect("Invalid ship id");
+        let user_id = serde_json::from_value::<User>(json!(2)).expect("Invalid user id");
+        let module_id =
+            serde_json::from_value::<Module>(json!({ "Sampler": 3 })).expect("Invalid module id");
+
+        let requirements = match SamplerStats::from_properties(&[0; 5]) {
+            Ok(stats) => stats.get_requirements(),
+            Err(_) => panic!("Invalid sampler properties"),
+        };
+        let (resources, quantities): (Vec<Resource>, Vec<NonZeroU32>) =
+            requirements.into_iter().unzip();
+
+        let mut registry = Registry::new();
+
+        registry.insert(Arc::new(Entities::new(4)));
+        registry.insert(Mailbox::<SpatialOSMsg>::new(64, Overflow::Block));
+        registry.insert(Mailbox::<DatabaseMsg>::new(64, Overflow::Block));
+        registry.insert(Mailbox::<CraftingMsg>::new(64, Overflow::Block));
+
+        identifications::System::register(&mut registry);
+        crafting::System::register(&mut registry);
+        System::register(&mut registry);
+
+        identifications::System::init(&registry)
+            .handle_message(IdMsg::AddComponent(ship_id, user_id));
+        crafting::System::init(&registry)
+            .handle_message(CraftingMsg::AddComponent(ship_id, [0; 5]));
+
+        let mut inventory = Inventory::new(4);
+
+        inventory.give_resources(&resources, &quantities);
+
+        let mut system = System::init(&registry);
+
+        system.handle_message(SystemMessage::AddComponent(ship_id, inventory));
+        system.handle_message(SystemMessage::CraftModule(
+            ship_id,
+            module_id,
+            String::from("Sampler"),
+            vec![0; 5],
+            resources.clone(),
+        ));
+
+        let database = registry.get::<Mailbox<DatabaseMsg>>().receiver();
+        let mut stored = None;
+
+        while let Some(envelope) = database.try_recv() {
+            if let Envelope::Message(DatabaseMsg::UpdateInventory(_, inventory)) = envelope {
+                stored = Some(inventory);
+            }
+        }
+
+        let stored = stored.expect("Inventory not saved");
+
+        //The queue outlives the restart, the dispatcher replays the stored inventory
+        let mut system = System::init(&registry);
+
+        system.handle_message(SystemMessage::AddComponent(ship_id, stored));
+
+        let inventory = system.inventories.read(&ship_id).expect("No inventory");
+
+        assert!(resources
+            .iter()
+            .all(|resource_id| inventory.resources.quantity(resource_id) == 0));
+
+        system.handle_message(SystemMessage::CancelCraft(ship_id, module_id));
+
+        let inventory = system.inventories.read(&ship_id).expect("No inventory");
+
+        for (resource_id, quantity) in resources.iter().zip(quantities.iter()) {
+            assert_eq!(inventory.resources.quantity(resource_id), quantity.get());
         }
     }
 }
@@ -1,14 +1,16 @@
 use procedural_generation::id_types::{Module, Resource, User};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::num::NonZeroI32;
 use std::num::NonZeroU32;
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Modules {
+    #[serde(with = "crate::inventory::pairs")]
     pub modules: HashMap<Module, ModuleStats>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ModuleStats {
     name: String,
     creator: User,
@@ -16,10 +18,12 @@ pub struct ModuleStats {
     resources: ModuleResources,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ModuleResources {
     resource_ids: Vec<Resource>,
-    quantities: Vec<NonZeroU32>,
+    quantities: Vec<u32>,
+    //Quantities when crafted, repairs can't go above them
+    crafted: Vec<NonZeroU32>,
 }
 
 impl Modules {
@@ -33,6 +37,10 @@ impl Modules {
         self.modules.insert(*module_id, module);
     }
 
+    pub fn contains(&self, module_id: &Module) -> bool {
+        self.modules.contains_key(module_id)
+    }
+
     pub fn update_module_durability(&mut self, module_id: &Module, delta: i32) {
         let module_data = self.modules.get_mut(module_id);
         let module_data = match module_data {
@@ -48,18 +56,27 @@ impl Modules {
             self.modules.remove(module_id);
         }
     }
+
+    //Quantity of each resource the module lost since it was crafted
+    pub fn wear(&self, module_id: &Module) -> Option<Vec<(Resource, u32)>> {
+        self.modules
+            .get(module_id)
+            .map(|module_data| module_data.resources.wear())
+    }
+
+    pub fn repair(&mut self, module_id: &Module, amounts: &[u32]) {
+        if let Some(module_data) = self.modules.get_mut(module_id) {
+            module_data.resources.repair(amounts);
+        }
+    }
 }
 
 impl ModuleStats {
     pub fn new(name: String, creator: User, levels: &[u8], resources: ModuleResources) -> Self {
-        let mut properties = Vec::with_capacity(levels.len());
-
-        properties.copy_from_slice(levels);
-
         ModuleStats {
             name,
             creator,
-            properties,
+            properties: levels.to_vec(),
             resources,
         }
     }
@@ -71,15 +88,31 @@ impl ModuleStats {
 
 impl ModuleResources {
     pub fn new(input_resources: &[Resource], input_quantities: &[NonZeroU32]) -> Self {
-        let mut resource_ids = Vec::with_capacity(input_resources.len());
-        let mut quantities = Vec::with_capacity(input_quantities.len());
+        Self {
+            resource_ids: input_resources.to_vec(),
+            quantities: input_quantities
+                .iter()
+                .map(|quantity| quantity.get())
+                .collect(),
+            crafted: input_quantities.to_vec(),
+        }
+    }
 
-        resource_ids.copy_from_slice(input_resources);
-        quantities.copy_from_slice(input_quantities);
+    fn wear(&self) -> Vec<(Resource, u32)> {
+        self.resource_ids
+            .iter()
+            .zip(self.quantities.iter().zip(self.crafted.iter()))
+            .map(|(resource_id, (quantity, crafted))| {
+                (*resource_id, crafted.get().saturating_sub(*quantity))
+            })
+            .collect()
+    }
 
-        Self {
-            resource_ids,
-            quantities,
+    fn repair(&mut self, amounts: &[u32]) {
+        for (index, amount) in amounts.iter().enumerate() {
+            if let Some(amount) = NonZeroI32::new(*amount as i32) {
+                self.update_resource_quantity(index, amount);
+            }
         }
     }
 
@@ -88,11 +121,7 @@ impl ModuleResources {
             return true;
         }
 
-        let mut total = 0;
-
-        for quantity in self.quantities.iter() {
-            total += quantity.get();
-        }
+        let total: u32 = self.quantities.iter().sum();
 
         total > delta.abs() as u32
     }
@@ -100,11 +129,27 @@ impl ModuleResources {
     fn update_durability(&mut self, total_change: NonZeroI32) {
         let sign = total_change.get().signum();
 
-        let mut delta = total_change.get().abs() as u32 / self.quantities.len() as u32;
-        let mut remainder = total_change.get().abs() as u32 % self.quantities.len() as u32; // 0 <= X <= indices.len() - 1
+        //Wear only takes from the resources left, refunds only fill the ones worn
+        let indices = (0..self.quantities.len())
+            .filter(|index| {
+                if sign > 0 {
+                    self.quantities[*index] < self.crafted[*index].get()
+                } else {
+                    self.quantities[*index] > 0
+                }
+            })
+            .collect::<Vec<usize>>();
+
+        if indices.is_empty() {
+            return;
+        }
+
+        let share = total_change.get().abs() as u32 / indices.len() as u32;
+        let mut remainder = total_change.get().abs() as u32 % indices.len() as u32; // 0 <= X <= indices.len() - 1
+
+        for index in indices {
+            let mut delta = share;
 
-        for index in (self.quantities.len() - 1)..0 {
-            //reverse iterate because swap_remove
             if remainder > 0 {
                 delta += 1;
                 remainder -= 1;
@@ -116,31 +161,127 @@ impl ModuleResources {
         }
     }
 
+    //Worn out resources stay at zero, repairs restore them up to the crafted quantity
     fn update_resource_quantity(&mut self, index: usize, quantity: NonZeroI32) {
-        if quantity.get().is_positive() {
-            if let Some(result) =
-                NonZeroU32::new(self.quantities[index].get() + quantity.get() as u32)
-            {
-                self.quantities[index] = result;
-                return;
-            }
-        }
+        let current = self.quantities[index];
 
-        if let Some(result) = self.quantities[index]
-            .get()
-            .checked_sub(quantity.get().abs() as u32)
-        {
-            if let Some(result) = NonZeroU32::new(result) {
-                self.quantities[index] = result;
-                return;
-            }
-        }
+        self.quantities[index] = if quantity.get().is_positive() {
+            current
+                .saturating_add(quantity.get() as u32)
+                .min(self.crafted[index].get())
+        } else {
+            current.saturating_sub(quantity.get().abs() as u32)
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use procedural_generation::modules::samplers::SamplerStats;
+    use serde_json::json;
+
+    fn sampler_modules() -> (Module, Modules) {
+        let user_id = serde_json::from_value::<User>(json!(1)).expect("Invalid user id");
+        let module_id =
+            serde_json::from_value::<Module>(json!({ "Sampler": 1 })).expect("Invalid module id");
 
-        self.swap_remove(index);
+        let requirements = match SamplerStats::from_properties(&[0; 5]) {
+            Ok(stats) => stats.get_requirements(),
+            Err(_) => panic!("Invalid sampler properties"),
+        };
+        let (resources, quantities): (Vec<Resource>, Vec<NonZeroU32>) =
+            requirements.into_iter().unzip();
+
+        let module_res = ModuleResources::new(&resources, &quantities);
+        let module = ModuleStats::new("Sampler".to_string(), user_id, &[0; 5], module_res);
+
+        let mut modules = Modules::with_capacity(1);
+
+        modules.add(&module_id, module);
+
+        (module_id, modules)
+    }
+
+    fn durability(modules: &Modules, module_id: &Module) -> u32 {
+        modules.modules[module_id].resources.quantities.iter().sum()
+    }
+
+    #[test]
+    fn refund_restores_used_durability() {
+        let (module_id, mut modules) = sampler_modules();
+        let crafted = durability(&modules, &module_id);
+
+        modules.update_module_durability(&module_id, -1);
+
+        assert_eq!(durability(&modules, &module_id), crafted - 1);
+
+        modules.update_module_durability(&module_id, 1);
+
+        assert_eq!(durability(&modules, &module_id), crafted);
     }
 
-    fn swap_remove(&mut self, index: usize) {
-        self.resource_ids.swap_remove(index);
-        self.quantities.swap_remove(index);
+    #[test]
+    fn wear_is_spread_over_resources() {
+        let (module_id, mut modules) = sampler_modules();
+        let crafted = durability(&modules, &module_id);
+
+        modules.update_module_durability(&module_id, -2);
+
+        assert_eq!(durability(&modules, &module_id), crafted - 2);
+    }
+
+    #[test]
+    fn module_is_removed_once_worn_out() {
+        let (module_id, mut modules) = sampler_modules();
+        let crafted = durability(&modules, &module_id);
+
+        modules.update_module_durability(&module_id, -(crafted as i32));
+
+        assert!(!modules.modules.contains_key(&module_id));
+    }
+
+    #[test]
+    fn repair_restores_worn_out_resources() {
+        let (module_id, mut modules) = sampler_modules();
+        let crafted = durability(&modules, &module_id);
+
+        //The smallest resource is used up, the others keep some durability
+        let smallest = modules.modules[&module_id]
+            .resources
+            .quantities
+            .iter()
+            .copied()
+            .min()
+            .expect("Sampler without resources");
+        let resources = modules.modules[&module_id].resources.quantities.len() as u32;
+
+        modules.update_module_durability(&module_id, -((smallest * resources) as i32));
+
+        assert!(modules.modules[&module_id]
+            .resources
+            .quantities
+            .contains(&0));
+
+        let amounts = modules
+            .wear(&module_id)
+            .expect("Module kept")
+            .into_iter()
+            .map(|(_, missing)| missing)
+            .collect::<Vec<u32>>();
+
+        modules.repair(&module_id, &amounts);
+
+        assert_eq!(durability(&modules, &module_id), crafted);
+    }
+
+    #[test]
+    fn repair_is_capped_at_crafted_quantities() {
+        let (module_id, mut modules) = sampler_modules();
+        let crafted = durability(&modules, &module_id);
+
+        modules.update_module_durability(&module_id, 5);
+
+        assert_eq!(durability(&modules, &module_id), crafted);
     }
 }
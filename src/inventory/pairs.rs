@@ -0,0 +1,40 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+use std::hash::Hash;
+
+//Maps keyed by ids are stored as a list of entries, JSON object keys can only be strings
+//and Firestore refuses an array directly inside another array
+#[derive(Serialize)]
+struct EntryRef<'a, K, V> {
+    id: &'a K,
+    value: &'a V,
+}
+
+#[derive(Deserialize)]
+struct Entry<K, V> {
+    id: K,
+    value: V,
+}
+
+pub fn serialize<K, V, S>(map: &HashMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    K: Serialize,
+    V: Serialize,
+    S: Serializer,
+{
+    serializer.collect_seq(map.iter().map(|(id, value)| EntryRef { id, value }))
+}
+
+pub fn deserialize<'de, K, V, D>(deserializer: D) -> Result<HashMap<K, V>, D::Error>
+where
+    K: Deserialize<'de> + Eq + Hash,
+    V: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    let entries = Vec::<Entry<K, V>>::deserialize(deserializer)?;
+
+    Ok(entries
+        .into_iter()
+        .map(|entry| (entry.id, entry.value))
+        .collect())
+}
@@ -0,0 +1,234 @@
+use crate::ecs::storage::Storage;
+use crate::ecs::Entities;
+use procedural_generation::id_types::{Module, Resource, Ship, User};
+use std::collections::VecDeque;
+use std::num::NonZeroU32;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const CRAFT_TIME_PER_UNIT: Duration = Duration::from_millis(100);
+
+// A module being crafted, its resources are already taken from the inventory.
+pub struct CraftJob {
+    pub module_id: Module,
+    pub name: String,
+    pub creator: User,
+    pub properties: Vec<u8>,
+    pub resources: Vec<Resource>,
+    pub quantities: Vec<NonZeroU32>,
+
+    duration: Duration,
+    //Set once the job is at the front of the queue
+    started: Option<Instant>,
+    progress: u8,
+}
+
+impl CraftJob {
+    pub fn new(
+        module_id: Module,
+        name: String,
+        creator: User,
+        properties: Vec<u8>,
+        resources: Vec<Resource>,
+        quantities: Vec<NonZeroU32>,
+    ) -> Self {
+        //More resources required, longer to build
+        let units = quantities
+            .iter()
+            .fold(0u32, |total, quantity| total.saturating_add(quantity.get()));
+
+        Self {
+            module_id,
+            name,
+            creator,
+            properties,
+            resources,
+            quantities,
+
+            duration: CRAFT_TIME_PER_UNIT * units,
+            started: None,
+            progress: 0,
+        }
+    }
+}
+
+pub enum Progress {
+    Unchanged,
+    Progressed(Module, u8),
+    Completed(CraftJob),
+}
+
+// Modules waiting to be crafted by each ship, built one after the other.
+pub struct CraftingQueues {
+    data: Storage<VecDeque<CraftJob>>,
+}
+
+impl CraftingQueues {
+    pub fn new(entities: Arc<Entities>) -> Self {
+        Self {
+            data: Storage::new(entities),
+        }
+    }
+
+    pub fn push(&self, ship_id: &Ship, job: CraftJob) {
+        let mut job = Some(job);
+
+        self.data.update(ship_id, |queue| {
+            queue.push_back(job.take().expect("Job not pushed yet"));
+        });
+
+        if let Some(job) = job {
+            let mut queue = VecDeque::with_capacity(1);
+
+            queue.push_back(job);
+
+            self.data.insert(ship_id, queue);
+        }
+    }
+
+    pub fn contains(&self, ship_id: &Ship, module_id: &Module) -> bool {
+        self.data
+            .with(ship_id, |queue| {
+                queue.iter().any(|job| job.module_id == *module_id)
+            })
+            .unwrap_or(false)
+    }
+
+    pub fn cancel(&self, ship_id: &Ship, module_id: &Module) -> Option<CraftJob> {
+        self.data
+            .update(ship_id, |queue| {
+                let index = queue.iter().position(|job| job.module_id == *module_id)?;
+
+                let job = queue.remove(index)?;
+
+                //Cancelling the job being built starts the next one
+                if index == 0 {
+                    if let Some(next) = queue.front_mut() {
+                        next.started = None;
+                    }
+                }
+
+                Some(job)
+            })
+            .flatten()
+    }
+
+    pub fn remove(&self, ship_id: &Ship) -> Vec<CraftJob> {
+        self.data
+            .remove(ship_id)
+            .map(|queue| queue.into_iter().collect())
+            .unwrap_or_default()
+    }
+
+    //Resources taken from the inventory by every queued job of the ship
+    pub fn reserved(&self, ship_id: &Ship) -> Vec<(Resource, NonZeroU32)> {
+        self.data
+            .with(ship_id, |queue| {
+                queue
+                    .iter()
+                    .flat_map(|job| {
+                        job.resources
+                            .iter()
+                            .copied()
+                            .zip(job.quantities.iter().copied())
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn ships(&self) -> Vec<Ship> {
+        let mut ships = Vec::new();
+
+        self.data.for_each(|ship_id, queue| {
+            if !queue.is_empty() {
+                ships.push(*ship_id);
+            }
+        });
+
+        ships
+    }
+
+    pub fn advance(&self, ship_id: &Ship, now: Instant) -> Progress {
+        self.data
+            .update(ship_id, |queue| {
+                let job = match queue.front_mut() {
+                    Some(job) => job,
+                    None => return Progress::Unchanged,
+                };
+
+                let elapsed = now.duration_since(*job.started.get_or_insert(now));
+
+                if elapsed >= job.duration {
+                    return Progress::Completed(queue.pop_front().expect("Job at the front"));
+                }
+
+                let progress = (elapsed.as_millis() * 100 / job.duration.as_millis()) as u8;
+
+                if progress == job.progress {
+                    return Progress::Unchanged;
+                }
+
+                job.progress = progress;
+
+                Progress::Progressed(job.module_id, progress)
+            })
+            .unwrap_or(Progress::Unchanged)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use procedural_generation::modules::samplers::SamplerStats;
+    use serde_json::json;
+
+    fn sampler_job(module_id: Module, requirements: &[(Resource, NonZeroU32)]) -> CraftJob {
+        let user_id = serde_json::from_value::<User>(json!(1)).expect("Invalid user id");
+        let (resources, quantities) = requirements.iter().copied().unzip();
+
+        CraftJob::new(
+            module_id,
+            String::from("Sampler"),
+            user_id,
+            vec![0; 5],
+            resources,
+            quantities,
+        )
+    }
+
+    fn total(reserved: &[(Resource, NonZeroU32)]) -> u32 {
+        reserved.iter().map(|(_, quantity)| quantity.get()).sum()
+    }
+
+    #[test]
+    fn reserved_resources_follow_the_queued_jobs() {
+        let queues = CraftingQueues::new(Arc::new(Entities::new(4)));
+        let ship_id = serde_json::from_value::<Ship>(json!(1)).expect("Invalid ship id");
+        let other_id = serde_json::from_value::<Ship>(json!(2)).expect("Invalid ship id");
+        let first =
+            serde_json::from_value::<Module>(json!({ "Sampler": 1 })).expect("Invalid module id");
+        let second =
+            serde_json::from_value::<Module>(json!({ "Sampler": 2 })).expect("Invalid module id");
+
+        let requirements = match SamplerStats::from_properties(&[0; 5]) {
+            Ok(stats) => stats.get_requirements(),
+            Err(_) => panic!("Invalid sampler properties"),
+        };
+        let job_total = total(&requirements);
+
+        queues.push(&ship_id, sampler_job(first, &requirements));
+        queues.push(&ship_id, sampler_job(second, &requirements));
+
+        assert_eq!(total(&queues.reserved(&ship_id)), 2 * job_total);
+        assert!(queues.reserved(&other_id).is_empty());
+
+        assert!(queues.cancel(&ship_id, &first).is_some());
+
+        assert_eq!(total(&queues.reserved(&ship_id)), job_total);
+
+        queues.remove(&ship_id);
+
+        assert!(queues.reserved(&ship_id).is_empty());
+    }
+}
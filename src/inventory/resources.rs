@@ -1,9 +1,11 @@
 use procedural_generation::id_types::Resource;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::num::NonZeroU32;
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Resources {
+    #[serde(with = "crate::inventory::pairs")]
     resources: HashMap<Resource, NonZeroU32>,
 }
 
@@ -14,6 +16,12 @@ impl Resources {
         }
     }
 
+    pub fn quantity(&self, resource_id: &Resource) -> u32 {
+        self.resources
+            .get(resource_id)
+            .map_or(0, |quantity| quantity.get())
+    }
+
     pub fn has_enough(&self, resource_id: &Resource, quantity: NonZeroU32) -> bool {
         let res_quantity = self.resources.get(resource_id);
         match res_quantity {
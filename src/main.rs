@@ -1,74 +1,70 @@
 mod database;
+mod ecs;
 mod inventory;
 mod modules;
 mod ships;
 mod spatial_os;
+mod systems;
 
-fn main() {
-    let mut handles = vec![];
-
-    let (handle, spatial_os) = spatial_os::connexion::System::init();
-    handles.push(handle);
+use database::Settings as DatabaseSettings;
+use ecs::Entities;
+use modules::{cooldowns, samplers, scanners, sensors};
+use ships::{damageables, exploration, heartbeats, identifications, positions};
+use spatial_os::connexion::{CommandPolicies, SystemMessage as SpatialOSMsg};
+use spatial_os::simulation::Simulation;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use systems::config::Config;
+use systems::mailbox::Mailbox;
+use systems::runner::Builder;
 
-    let (handle, database) = database::firestore::System::init();
-    handles.push(handle);
+fn main() {
+    let (signal, shutdown) = mpsc::channel();
 
-    let (handle, _id_system, identifiers) = ships::identifications::System::init(100);
-    handles.push(handle);
+    ctrlc::set_handler(move || {
+        let _ = signal.send(());
+    })
+    .expect("Error setting signal handler");
 
-    let (handle, _exploration_system, asteroids) = ships::exploration::System::init(100);
-    handles.push(handle);
+    let config = Config::default();
 
-    let (handle, _heartbeat_system) =
-        ships::heartbeats::System::init(100, 30_000, spatial_os.clone());
-    handles.push(handle);
+    //Outlives the connexion system, restarting it reconnects to the same runtime
+    let runtime = Arc::new(Mutex::new(Simulation::new(config.capacity)));
 
-    let (handle, _positions_system, positions) =
-        ships::positions::System::init(100, 900_000, spatial_os.clone(), database.clone());
-    handles.push(handle);
+    let entities = Arc::new(Entities::new(config.max_entities));
 
-    let (handle, cooldown_system, cooldowns) = modules::cooldowns::System::init(1000);
-    handles.push(handle);
+    let mut systems = Builder::new()
+        .resource(config)
+        .resource(entities)
+        .resource(runtime)
+        .resource(CommandPolicies::default())
+        .resource(DatabaseSettings::from_env())
+        .system::<spatial_os::connexion::System>()
+        .system::<database::System>()
+        .system::<identifications::System>()
+        .system::<exploration::System>()
+        .system::<heartbeats::System>()
+        .system::<positions::System>()
+        .system::<cooldowns::System>()
+        .system::<inventory::crafting::System>()
+        .system::<inventory::System>()
+        .system::<sensors::System>()
+        .system::<scanners::System>()
+        .system::<samplers::System>()
+        .system::<damageables::System>()
+        .system::<spatial_os::init::System>()
+        .start();
 
-    let (handle, inventory_system, inventories) =
-        inventory::System::init(100, spatial_os.clone(), identifiers.clone());
-    handles.push(handle);
+    //Restarted systems lost their state, SpatialOS sends every entity again
+    let spatial_os = systems.registry().get::<Mailbox<SpatialOSMsg>>();
 
-    let (handle, _sensors_system) = modules::sensors::System::init(
-        100,
-        spatial_os.clone(),
-        cooldown_system.clone(),
-        inventory_system.clone(),
-        positions.clone(),
-        cooldowns.clone(),
-        inventories.clone(),
-    );
-    handles.push(handle);
+    systems.on_restart(move || {
+        spatial_os.send(SpatialOSMsg::Resync);
+    });
 
-    let (handle, _scanners_system) = modules::scanners::System::init(
-        100,
-        spatial_os.clone(),
-        cooldown_system.clone(),
-        inventory_system.clone(),
-        asteroids.clone(),
-        cooldowns.clone(),
-        identifiers.clone(),
-        inventories.clone(),
-    );
-    handles.push(handle);
+    systems.run(&shutdown);
 
-    let (handle, _samplers_system) = modules::samplers::System::init(
-        100,
-        spatial_os.clone(),
-        cooldown_system.clone(),
-        inventory_system.clone(),
-        asteroids.clone(),
-        cooldowns.clone(),
-        inventories.clone(),
-    );
-    handles.push(handle);
+    println!("Shutting down");
 
-    let (handle, _damageable_system, _damageables) =
-        ships::damageables::System::init(100, spatial_os.clone());
-    handles.push(handle);
+    systems.shutdown();
 }
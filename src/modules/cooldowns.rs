@@ -1,82 +1,72 @@
+use crate::systems;
+use crate::systems::config::Config;
+use crate::systems::registry::Registry;
 use procedural_generation::id_types::Module;
 use std::collections::HashMap;
-use std::sync::mpsc;
-use std::sync::mpsc::{Receiver, Sender};
-use std::sync::{Arc, RwLock};
-use std::thread;
-use std::thread::JoinHandle;
-use std::time::{Duration, Instant};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::Duration;
+
+const FRAME_TIME: Duration = Duration::from_millis(1000 / 20);
 
 pub enum SystemMessage {
     AddOrUpdateComponent(Module, Duration),
     RemoveComponent(Module),
 
     StartTimer(Module),
+    StopTimer(Module),
 }
 
 pub struct System {
-    channel: Receiver<SystemMessage>,
-
     cold_data: HashMap<Module, Duration>,
     hot_data: ActiveCooldowns,
 }
 
-impl System {
-    pub fn init(capacity: usize) -> (JoinHandle<()>, Sender<SystemMessage>, Arc<Cooldowns>) {
-        let (tx, rx) = mpsc::channel();
+impl systems::System for System {
+    type Message = SystemMessage;
 
-        let mut system = Self {
-            channel: rx,
-
-            cold_data: HashMap::with_capacity(capacity),
-            hot_data: ActiveCooldowns::new(capacity / 10),
-        };
+    const NAME: &'static str = "cooldowns";
 
-        let arc = Arc::clone(&system.hot_data.module_ids);
+    fn register(registry: &mut Registry) {
+        let config = registry.get::<Config>();
 
-        let handle = thread::spawn(move || {
-            let frame_rate = 20;
-            let frame_time = Duration::from_millis(1000 / frame_rate);
-            //GameLoop
-            loop {
-                let before_frame = Instant::now();
-
-                system.update(&frame_time);
-
-                let after_frame = Instant::now();
+        registry.insert(Arc::new(Cooldowns::new(config.cooldown_capacity / 10)));
+    }
 
-                let frame_duration = after_frame.duration_since(before_frame);
+    fn init(registry: &Registry) -> Self {
+        let config = registry.get::<Config>();
 
-                if let Some(delta_time) = frame_time.checked_sub(frame_duration) {
-                    std::thread::sleep(delta_time);
-                } else {
-                    println!(
-                        "The frame took {:?}, too long couldn't sleep",
-                        frame_duration
-                    );
-                }
-            }
-        });
-
-        (handle, tx, arc)
+        Self {
+            cold_data: HashMap::with_capacity(config.cooldown_capacity),
+            hot_data: ActiveCooldowns::new(
+                registry.get::<Arc<Cooldowns>>(),
+                config.cooldown_capacity / 10,
+            ),
+        }
     }
 
-    fn update(&mut self, delta_time: &Duration) {
-        if let Ok(result) = self.channel.try_recv() {
-            match result {
-                SystemMessage::AddOrUpdateComponent(module_id, duration) => {
-                    self.cold_data.insert(module_id, duration);
-                }
-                SystemMessage::RemoveComponent(module_id) => {
-                    self.cold_data.remove(&module_id);
-                }
-                SystemMessage::StartTimer(module_id) => self.start_timer(&module_id),
+    fn handle_message(&mut self, message: SystemMessage) {
+        match message {
+            SystemMessage::AddOrUpdateComponent(module_id, duration) => {
+                self.cold_data.insert(module_id, duration);
             }
+            SystemMessage::RemoveComponent(module_id) => {
+                self.cold_data.remove(&module_id);
+            }
+            SystemMessage::StartTimer(module_id) => self.start_timer(&module_id),
+            SystemMessage::StopTimer(module_id) => self.hot_data.stop_timer(&module_id),
         }
+    }
 
-        self.hot_data.update_timers(delta_time);
+    fn frame_time(&self) -> Option<Duration> {
+        Some(FRAME_TIME)
     }
 
+    fn tick(&mut self) {
+        self.hot_data.update_timers(&FRAME_TIME);
+    }
+}
+
+impl System {
     fn start_timer(&mut self, module_id: &Module) {
         let time = self.cold_data.get(module_id);
         let time = match time {
@@ -96,41 +86,68 @@ struct ActiveCooldowns {
 const ZERO_DURATION: Duration = Duration::from_nanos(0);
 
 impl ActiveCooldowns {
-    fn new(capacity: usize) -> Self {
+    fn new(module_ids: Arc<Cooldowns>, capacity: usize) -> Self {
+        //Timers don't survive a restart, active cooldowns are dropped with them
+        module_ids
+            .data
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+
         Self {
-            module_ids: Arc::new(Cooldowns::new(capacity)),
+            module_ids,
             timers: Vec::with_capacity(capacity),
         }
     }
 
     fn start_timer(&mut self, module_id: &Module, time: &Duration) {
-        if let Ok(mut module_ids) = self.module_ids.data.write() {
-            module_ids.push(*module_id);
-        }
+        let mut module_ids = self
+            .module_ids
+            .data
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        module_ids.push(*module_id);
 
         self.timers.push(*time);
     }
 
+    fn stop_timer(&mut self, module_id: &Module) {
+        let mut module_ids = self
+            .module_ids
+            .data
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        if let Some(index) = module_ids.iter().position(|id| *id == *module_id) {
+            module_ids.swap_remove(index);
+
+            self.timers.swap_remove(index);
+        }
+    }
+
     fn update_timers(&mut self, delta_time: &Duration) {
+        let mut module_ids = self
+            .module_ids
+            .data
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+
         let mut i = self.timers.len();
 
         while i != 0 {
             i -= 1; //iterate in reverse because of swap_remove
 
-            if let Some(result) = self.timers[i - 1].checked_sub(*delta_time) {
+            if let Some(result) = self.timers[i].checked_sub(*delta_time) {
                 if result != ZERO_DURATION {
-                    self.timers[i - 1] = result;
-
-                    i -= 1;
+                    self.timers[i] = result;
                     continue;
                 }
             }
 
-            if let Ok(mut module_ids) = self.module_ids.data.write() {
-                module_ids.swap_remove(i - 1);
-            }
+            module_ids.swap_remove(i);
 
-            self.timers.swap_remove(i - 1);
+            self.timers.swap_remove(i);
         }
     }
 }
@@ -147,7 +164,7 @@ impl Cooldowns {
     }
 
     pub fn is_active(&self, module_id: &Module) -> bool {
-        let module_ids = self.data.read().expect("Lock poisoned");
+        let module_ids = self.data.read().unwrap_or_else(PoisonError::into_inner);
 
         for id in module_ids.iter() {
             if *id == *module_id {
@@ -1,86 +1,93 @@
 use crate::inventory::{Inventories, SystemMessage as InvMsg};
 use crate::modules::cooldowns::{Cooldowns, SystemMessage as CooldownMsg};
 use crate::ships::exploration::Asteroids;
-use crate::spatial_os::connexion::{CommandRequest, SystemMessage as SpatialOSMsg};
+use crate::spatial_os::connexion::{CommandError, CommandRequest, SystemMessage as SpatialOSMsg};
+use crate::systems;
+use crate::systems::config::Config;
+use crate::systems::mailbox::{Mailbox, Overflow};
+use crate::systems::registry::Registry;
 use procedural_generation::id_types::{Module, Resource, Ship};
 use procedural_generation::modules::samplers::SamplerStats;
 use std::collections::HashMap;
-use std::sync::mpsc;
-use std::sync::mpsc::{Receiver, Sender};
 use std::sync::Arc;
-use std::thread;
-use std::thread::JoinHandle;
+
+//Durability used by one extraction, given back if it fails
+const DURABILITY_COST: i32 = 1;
 
 pub enum SystemMessage {
     AddComponent(Ship, Module),
     UpdateComponent(Ship, Module),
     RemoveComponent(Ship),
 
-    CommandResponse(Ship, Resource, u32),
+    CommandResponse(Ship, Result<(Resource, u32), CommandError>),
 
-    UseScanner(Ship),
+    UseSampler(Ship),
 }
 
 pub struct System {
-    channel: Receiver<SystemMessage>,
-    spatial_os: Sender<SpatialOSMsg>,
-    cooldown: Sender<CooldownMsg>,
-    inventory: Sender<InvMsg>,
+    spatial_os: Mailbox<SpatialOSMsg>,
+    cooldown: Mailbox<CooldownMsg>,
+    inventory: Mailbox<InvMsg>,
 
     samplers: HashMap<Ship, Module>,
+    //Module used by the extraction of each ship awaiting its response
+    extracting: HashMap<Ship, Module>,
 
     asteroids: Arc<Asteroids>,
     cooldowns: Arc<Cooldowns>,
     inventories: Arc<Inventories>,
 }
 
-impl System {
-    pub fn init(
-        capacity: usize,
-        spatial_os: Sender<SpatialOSMsg>,
-        cooldown: Sender<CooldownMsg>,
-        inventory: Sender<InvMsg>,
-        asteroids: Arc<Asteroids>,
-        cooldowns: Arc<Cooldowns>,
-        inventories: Arc<Inventories>,
-    ) -> (JoinHandle<()>, Sender<SystemMessage>) {
-        let (tx, rx) = mpsc::channel();
-
-        let mut system = Self {
-            channel: rx,
-            spatial_os,
-            cooldown,
-            inventory,
-
-            samplers: HashMap::with_capacity(capacity),
-            asteroids,
-            cooldowns,
-            inventories,
-        };
+impl systems::System for System {
+    type Message = SystemMessage;
+
+    const NAME: &'static str = "samplers";
 
-        let handle = thread::spawn(move || {
-            system.update_loop();
-        });
+    fn init(registry: &Registry) -> Self {
+        let config = registry.get::<Config>();
 
-        (handle, tx)
+        Self {
+            spatial_os: registry.get::<Mailbox<SpatialOSMsg>>(),
+            cooldown: registry.get::<Mailbox<CooldownMsg>>(),
+            inventory: registry.get::<Mailbox<InvMsg>>(),
+
+            samplers: HashMap::with_capacity(config.capacity),
+            extracting: HashMap::with_capacity(config.capacity),
+            asteroids: registry.get::<Arc<Asteroids>>(),
+            cooldowns: registry.get::<Arc<Cooldowns>>(),
+            inventories: registry.get::<Arc<Inventories>>(),
+        }
     }
 
-    fn update_loop(&mut self) {
-        while let Ok(result) = self.channel.recv() {
-            match result {
-                SystemMessage::AddComponent(id, data) => self.add_component(&id, &data),
-                SystemMessage::UpdateComponent(id, data) => self.update_component(&id, &data),
-                SystemMessage::RemoveComponent(id) => self.remove_component(&id),
-                SystemMessage::CommandResponse(ship_id, resource_id, quantity) => {
-                    self.process_response(&ship_id, &resource_id, quantity)
-                }
-                SystemMessage::UseScanner(id) => self.use_scanner(&id),
+    //Using the same module again while the request is queued does nothing
+    fn overflow() -> Overflow<SystemMessage> {
+        Overflow::Coalesce(|queued, message| match (queued, message) {
+            (SystemMessage::UseSampler(queued_id), SystemMessage::UseSampler(ship_id)) => {
+                queued_id == ship_id
             }
+            _ => false,
+        })
+    }
+
+    fn handle_message(&mut self, message: SystemMessage) {
+        match message {
+            SystemMessage::AddComponent(id, data) => self.add_component(&id, &data),
+            SystemMessage::UpdateComponent(id, data) => self.update_component(&id, &data),
+            SystemMessage::RemoveComponent(id) => self.remove_component(&id),
+            SystemMessage::CommandResponse(ship_id, result) => {
+                self.process_response(&ship_id, result)
+            }
+            SystemMessage::UseSampler(id) => self.use_sampler(&id),
         }
     }
+}
 
+impl System {
     fn add_component(&mut self, id: &Ship, data: &Module) {
         self.samplers.insert(*id, *data);
+
+        //Added again on resync, a request lost with a restarted connexion is never answered
+        self.extracting.remove(id);
     }
 
     fn update_component(&mut self, id: &Ship, data: &Module) {
@@ -89,20 +96,26 @@ impl System {
 
     fn remove_component(&mut self, id: &Ship) {
         self.samplers.remove(&id);
+        self.extracting.remove(id);
     }
 
-    fn use_scanner(&self, ship_id: &Ship) {
+    fn use_sampler(&mut self, ship_id: &Ship) {
+        //A ship waits for the answer before using a module again
+        if self.extracting.contains_key(ship_id) {
+            return;
+        }
+
         let sampler_id = self.samplers.get(ship_id);
         let sampler_id = match sampler_id {
-            Some(sampler_id) => sampler_id,
+            Some(sampler_id) => *sampler_id,
             None => return,
         };
 
-        if self.cooldowns.is_active(sampler_id) {
+        if self.cooldowns.is_active(&sampler_id) {
             return;
         }
 
-        let props = self.inventories.get_module_properties(ship_id, sampler_id);
+        let props = self.inventories.get_module_properties(ship_id, &sampler_id);
         let sampler = match props {
             Some(props) => match SamplerStats::from_properties(&props) {
                 Ok(sampler) => sampler,
@@ -117,34 +130,51 @@ impl System {
             None => return,
         };
 
-        let message = CooldownMsg::StartTimer(*sampler_id);
+        let message = CooldownMsg::StartTimer(sampler_id);
 
-        self.cooldown
-            .send(message)
-            .expect("Cooldown system terminated");
+        self.cooldown.send(message);
 
-        let message = InvMsg::UpdateModuleDurability(*ship_id, *sampler_id, -1);
+        let message = InvMsg::UpdateModuleDurability(*ship_id, sampler_id, -DURABILITY_COST);
 
-        self.inventory
-            .send(message)
-            .expect("Inventory system terminated");
+        self.inventory.send(message);
 
         let message = SpatialOSMsg::CommandRequest(CommandRequest::ExtractResource(
             asteroid, *ship_id, sampler,
         ));
 
-        self.spatial_os
-            .send(message)
-            .expect("SpatialOS connexion terminated");
+        self.spatial_os.send(message);
+
+        self.extracting.insert(*ship_id, sampler_id);
     }
 
-    fn process_response(&self, ship_id: &Ship, resource_id: &Resource, quantity: u32) {
-        //TODO guard clause if command success
+    fn process_response(&mut self, ship_id: &Ship, result: Result<(Resource, u32), CommandError>) {
+        let module_id = self.extracting.remove(ship_id);
+
+        let (resource_id, quantity) = match result {
+            Ok(result) => result,
+            Err(error) => {
+                println!("Resource extraction failed: {}", error);
+
+                if let Some(module_id) = module_id {
+                    self.refund(ship_id, &module_id);
+                }
+                return;
+            }
+        };
+
+        let message = InvMsg::AddOrUpdateResource(*ship_id, resource_id, quantity as i32);
+
+        self.inventory.send(message);
+    }
+
+    //Given back to the module that was used, even if another one is equipped since
+    fn refund(&self, ship_id: &Ship, module_id: &Module) {
+        let message = CooldownMsg::StopTimer(*module_id);
+
+        self.cooldown.send(message);
 
-        let message = InvMsg::AddOrUpdateResource(*ship_id, *resource_id, quantity as i32);
+        let message = InvMsg::UpdateModuleDurability(*ship_id, *module_id, DURABILITY_COST);
 
-        self.inventory
-            .send(message)
-            .expect("Inventory system terminated");
+        self.inventory.send(message);
     }
 }
@@ -3,34 +3,38 @@ use crate::modules::cooldowns::{Cooldowns, SystemMessage as CooldownMsg};
 use crate::ships::exploration::Asteroids;
 use crate::ships::identifications::Identifiers;
 use crate::spatial_os::connexion::{
-    CommandRequest, SystemMessage as SpatialOSMsg, UpdateComponent,
+    CommandError, CommandRequest, SystemMessage as SpatialOSMsg, UpdateComponent,
 };
+use crate::systems;
+use crate::systems::config::Config;
+use crate::systems::mailbox::{Mailbox, Overflow};
+use crate::systems::registry::Registry;
 use procedural_generation::id_types::{Module, Resource, Ship};
 use procedural_generation::modules::scanners::ScannerStats;
 use std::collections::HashMap;
-use std::sync::mpsc;
-use std::sync::mpsc::{Receiver, Sender};
 use std::sync::Arc;
-use std::thread;
-use std::thread::JoinHandle;
+
+//Durability used by one scan, given back if it fails
+const DURABILITY_COST: i32 = 1;
 
 pub enum SystemMessage {
     AddComponent(Ship, Module),
     UpdateComponent(Ship, Module),
     RemoveComponent(Ship),
 
-    CommandResponse(Ship, Resource, u32),
+    CommandResponse(Ship, Result<(Resource, u32), CommandError>),
 
     UseScanner(Ship),
 }
 
 pub struct System {
-    channel: Receiver<SystemMessage>,
-    spatial_os: Sender<SpatialOSMsg>,
-    cooldown: Sender<CooldownMsg>,
-    inventory: Sender<InvMsg>,
+    spatial_os: Mailbox<SpatialOSMsg>,
+    cooldown: Mailbox<CooldownMsg>,
+    inventory: Mailbox<InvMsg>,
 
     scanners: HashMap<Ship, Module>,
+    //Module used by the scan of each ship awaiting its response
+    scanning: HashMap<Ship, Module>,
 
     asteroids: Arc<Asteroids>,
     cooldowns: Arc<Cooldowns>,
@@ -38,57 +42,58 @@ pub struct System {
     inventories: Arc<Inventories>,
 }
 
-impl System {
-    #[allow(clippy::too_many_arguments)]
-    pub fn init(
-        capacity: usize,
-        spatial_os: Sender<SpatialOSMsg>,
-        cooldown: Sender<CooldownMsg>,
-        inventory: Sender<InvMsg>,
-        asteroids: Arc<Asteroids>,
-        cooldowns: Arc<Cooldowns>,
-        identifiers: Arc<Identifiers>,
-        inventories: Arc<Inventories>,
-    ) -> (JoinHandle<()>, Sender<SystemMessage>) {
-        let (tx, rx) = mpsc::channel();
-
-        let mut system = Self {
-            channel: rx,
-            spatial_os,
-            cooldown,
-            inventory,
-
-            scanners: HashMap::with_capacity(capacity),
-
-            asteroids,
-            cooldowns,
-            identifiers,
-            inventories,
-        };
+impl systems::System for System {
+    type Message = SystemMessage;
+
+    const NAME: &'static str = "scanners";
+
+    fn init(registry: &Registry) -> Self {
+        let config = registry.get::<Config>();
+
+        Self {
+            spatial_os: registry.get::<Mailbox<SpatialOSMsg>>(),
+            cooldown: registry.get::<Mailbox<CooldownMsg>>(),
+            inventory: registry.get::<Mailbox<InvMsg>>(),
+
+            scanners: HashMap::with_capacity(config.capacity),
+            scanning: HashMap::with_capacity(config.capacity),
 
-        let handle = thread::spawn(move || {
-            system.update_loop();
-        });
+            asteroids: registry.get::<Arc<Asteroids>>(),
+            cooldowns: registry.get::<Arc<Cooldowns>>(),
+            identifiers: registry.get::<Arc<Identifiers>>(),
+            inventories: registry.get::<Arc<Inventories>>(),
+        }
+    }
 
-        (handle, tx)
+    //Using the same module again while the request is queued does nothing
+    fn overflow() -> Overflow<SystemMessage> {
+        Overflow::Coalesce(|queued, message| match (queued, message) {
+            (SystemMessage::UseScanner(queued_id), SystemMessage::UseScanner(ship_id)) => {
+                queued_id == ship_id
+            }
+            _ => false,
+        })
     }
 
-    fn update_loop(&mut self) {
-        while let Ok(result) = self.channel.recv() {
-            match result {
-                SystemMessage::AddComponent(id, data) => self.add_component(&id, &data),
-                SystemMessage::UpdateComponent(id, data) => self.update_component(&id, &data),
-                SystemMessage::RemoveComponent(id) => self.remove_component(&id),
-                SystemMessage::CommandResponse(ship_id, resource_id, quantity) => {
-                    self.process_response(&ship_id, &resource_id, quantity)
-                }
-                SystemMessage::UseScanner(id) => self.use_scanner(&id),
+    fn handle_message(&mut self, message: SystemMessage) {
+        match message {
+            SystemMessage::AddComponent(id, data) => self.add_component(&id, &data),
+            SystemMessage::UpdateComponent(id, data) => self.update_component(&id, &data),
+            SystemMessage::RemoveComponent(id) => self.remove_component(&id),
+            SystemMessage::CommandResponse(ship_id, result) => {
+                self.process_response(&ship_id, result)
             }
+            SystemMessage::UseScanner(id) => self.use_scanner(&id),
         }
     }
+}
 
+impl System {
     fn add_component(&mut self, id: &Ship, data: &Module) {
         self.scanners.insert(*id, *data);
+
+        //Added again on resync, a request lost with a restarted connexion is never answered
+        self.scanning.remove(id);
     }
 
     fn update_component(&mut self, id: &Ship, data: &Module) {
@@ -97,16 +102,22 @@ impl System {
 
     fn remove_component(&mut self, id: &Ship) {
         self.scanners.remove(&id);
+        self.scanning.remove(id);
     }
 
-    fn use_scanner(&self, ship_id: &Ship) {
+    fn use_scanner(&mut self, ship_id: &Ship) {
+        //A ship waits for the answer before using a module again
+        if self.scanning.contains_key(ship_id) {
+            return;
+        }
+
         let scanner_id = self.scanners.get(ship_id);
         let scanner_id = match scanner_id {
-            Some(scanner_id) => scanner_id,
+            Some(scanner_id) => *scanner_id,
             None => return,
         };
 
-        if self.cooldowns.is_active(scanner_id) {
+        if self.cooldowns.is_active(&scanner_id) {
             return;
         }
 
@@ -122,7 +133,7 @@ impl System {
             None => return,
         };
 
-        let props = self.inventories.get_module_properties(ship_id, scanner_id);
+        let props = self.inventories.get_module_properties(ship_id, &scanner_id);
         let scanner = match props {
             Some(props) => match ScannerStats::from_properties(&props) {
                 Ok(sampler) => sampler,
@@ -131,36 +142,54 @@ impl System {
             None => return,
         };
 
-        let message = CooldownMsg::StartTimer(*scanner_id);
+        let message = CooldownMsg::StartTimer(scanner_id);
+
+        self.cooldown.send(message);
 
-        self.cooldown
-            .send(message)
-            .expect("Cooldown system terminated");
+        let message = InvMsg::UpdateModuleDurability(*ship_id, scanner_id, -DURABILITY_COST);
 
-        let message = InvMsg::UpdateModuleDurability(*ship_id, *scanner_id, -1);
+        self.inventory.send(message);
 
-        self.inventory
-            .send(message)
-            .expect("Inventory system terminated");
+        let message = SpatialOSMsg::CommandRequest(CommandRequest::GenerateResource(
+            asteroid, *ship_id, user, scanner,
+        ));
 
-        let message =
-            SpatialOSMsg::CommandRequest(CommandRequest::GenerateResource(asteroid, user, scanner));
+        self.spatial_os.send(message);
 
-        self.spatial_os
-            .send(message)
-            .expect("SpatialOS connexion terminated");
+        self.scanning.insert(*ship_id, scanner_id);
     }
 
-    fn process_response(&self, ship_id: &Ship, resource_id: &Resource, quantity: u32) {
-        //TODO guard clause if command success
+    fn process_response(&mut self, ship_id: &Ship, result: Result<(Resource, u32), CommandError>) {
+        let module_id = self.scanning.remove(ship_id);
+
+        let (resource_id, quantity) = match result {
+            Ok(result) => result,
+            Err(error) => {
+                println!("Resource generation failed: {}", error);
+
+                if let Some(module_id) = module_id {
+                    self.refund(ship_id, &module_id);
+                }
+                return;
+            }
+        };
 
         let message = SpatialOSMsg::UpdateComponent(
             *ship_id,
-            UpdateComponent::Scanner(*resource_id, quantity),
+            UpdateComponent::Scanner(resource_id, quantity),
         );
 
-        self.spatial_os
-            .send(message)
-            .expect("SpatialOs connexion terminated");
+        self.spatial_os.send(message);
+    }
+
+    //Given back to the module that was used, even if another one is equipped since
+    fn refund(&self, ship_id: &Ship, module_id: &Module) {
+        let message = CooldownMsg::StopTimer(*module_id);
+
+        self.cooldown.send(message);
+
+        let message = InvMsg::UpdateModuleDurability(*ship_id, *module_id, DURABILITY_COST);
+
+        self.inventory.send(message);
     }
 }
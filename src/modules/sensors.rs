@@ -2,6 +2,10 @@ use crate::inventory::{Inventories, SystemMessage as InvMsg};
 use crate::modules::cooldowns::{Cooldowns, SystemMessage as CooldownMsg};
 use crate::ships::positions::Positions;
 use crate::spatial_os::connexion::{SystemMessage as SpatialMsg, UpdateComponent};
+use crate::systems;
+use crate::systems::config::Config;
+use crate::systems::mailbox::{Mailbox, Overflow};
+use crate::systems::registry::Registry;
 use nalgebra::Vector3;
 use procedural_generation::id_types::{Module, Ship};
 use procedural_generation::modules::sensors::SensorStats;
@@ -9,11 +13,7 @@ use procedural_generation::resources::quantity::get_tier;
 use procedural_generation::resources::rarity::get_samples;
 use rayon::prelude::*;
 use std::collections::HashMap;
-use std::sync::mpsc;
-use std::sync::mpsc::{Receiver, Sender};
 use std::sync::Arc;
-use std::thread;
-use std::thread::JoinHandle;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 pub enum SystemMessage {
@@ -25,10 +25,9 @@ pub enum SystemMessage {
 }
 
 pub struct System {
-    channel: Receiver<SystemMessage>,
-    spatial_os: Sender<SpatialMsg>,
-    cooldown: Sender<CooldownMsg>,
-    inventory: Sender<InvMsg>,
+    spatial_os: Mailbox<SpatialMsg>,
+    cooldown: Mailbox<CooldownMsg>,
+    inventory: Mailbox<InvMsg>,
 
     sensors: HashMap<Ship, Module>,
 
@@ -37,49 +36,48 @@ pub struct System {
     inventories: Arc<Inventories>,
 }
 
-impl System {
-    pub fn init(
-        capacity: usize,
-        spatial_os: Sender<SpatialMsg>,
-        cooldown: Sender<CooldownMsg>,
-        inventory: Sender<InvMsg>,
-        positions: Arc<Positions>,
-        cooldowns: Arc<Cooldowns>,
-        inventories: Arc<Inventories>,
-    ) -> (JoinHandle<()>, Sender<SystemMessage>) {
-        let (tx, rx) = mpsc::channel();
-
-        let mut system = Self {
-            channel: rx,
-            spatial_os,
-            cooldown,
-            inventory,
-
-            sensors: HashMap::with_capacity(capacity),
-
-            positions,
-            cooldowns,
-            inventories,
-        };
+impl systems::System for System {
+    type Message = SystemMessage;
+
+    const NAME: &'static str = "sensors";
 
-        let handle = thread::spawn(move || {
-            system.update_loop();
-        });
+    fn init(registry: &Registry) -> Self {
+        let config = registry.get::<Config>();
 
-        (handle, tx)
+        Self {
+            spatial_os: registry.get::<Mailbox<SpatialMsg>>(),
+            cooldown: registry.get::<Mailbox<CooldownMsg>>(),
+            inventory: registry.get::<Mailbox<InvMsg>>(),
+
+            sensors: HashMap::with_capacity(config.capacity),
+
+            positions: registry.get::<Arc<Positions>>(),
+            cooldowns: registry.get::<Arc<Cooldowns>>(),
+            inventories: registry.get::<Arc<Inventories>>(),
+        }
     }
 
-    fn update_loop(&mut self) {
-        while let Ok(result) = self.channel.recv() {
-            match result {
-                SystemMessage::AddComponent(id, data) => self.add_component(&id, &data),
-                SystemMessage::UpdateComponent(id, data) => self.update_component(&id, &data),
-                SystemMessage::RemoveComponent(id) => self.remove_component(&id),
-                SystemMessage::UseSensor(id) => self.use_sensor(&id),
+    //Using the same module again while the request is queued does nothing
+    fn overflow() -> Overflow<SystemMessage> {
+        Overflow::Coalesce(|queued, message| match (queued, message) {
+            (SystemMessage::UseSensor(queued_id), SystemMessage::UseSensor(ship_id)) => {
+                queued_id == ship_id
             }
+            _ => false,
+        })
+    }
+
+    fn handle_message(&mut self, message: SystemMessage) {
+        match message {
+            SystemMessage::AddComponent(id, data) => self.add_component(&id, &data),
+            SystemMessage::UpdateComponent(id, data) => self.update_component(&id, &data),
+            SystemMessage::RemoveComponent(id) => self.remove_component(&id),
+            SystemMessage::UseSensor(id) => self.use_sensor(&id),
         }
     }
+}
 
+impl System {
     fn add_component(&mut self, ship_id: &Ship, module_id: &Module) {
         self.sensors.insert(*ship_id, *module_id);
     }
@@ -120,15 +118,11 @@ impl System {
 
         let message = CooldownMsg::StartTimer(*sensor_id);
 
-        self.cooldown
-            .send(message)
-            .expect("Cooldown system terminated");
+        self.cooldown.send(message);
 
         let message = InvMsg::UpdateModuleDurability(*ship_id, *sensor_id, -1);
 
-        self.inventory
-            .send(message)
-            .expect("Inventory system terminated");
+        self.inventory.send(message);
 
         let time = SystemTime::now()
             .duration_since(UNIX_EPOCH)
@@ -161,8 +155,6 @@ impl System {
 
         let message = SpatialMsg::UpdateComponent(*ship_id, UpdateComponent::Sensor(samples));
 
-        self.spatial_os
-            .send(message)
-            .expect("SpatialOS connexion terminated");
+        self.spatial_os.send(message);
     }
 }
@@ -1,12 +1,13 @@
+use crate::database::SystemMessage as DatabaseMsg;
+use crate::ecs::storage::Storage;
+use crate::ecs::Entities;
 use crate::spatial_os::connexion::{SystemMessage as SpatialOSMsg, UpdateComponent};
+use crate::systems;
+use crate::systems::mailbox::Mailbox;
+use crate::systems::registry::Registry;
 use procedural_generation::id_types::Ship;
-use std::collections::HashMap;
 use std::num::NonZeroU32;
-use std::sync::mpsc;
-use std::sync::mpsc::{Receiver, Sender};
-use std::sync::{Arc, RwLock};
-use std::thread;
-use std::thread::JoinHandle;
+use std::sync::Arc;
 
 pub enum SystemMessage {
     AddComponent(Ship, NonZeroU32),
@@ -16,109 +17,99 @@ pub enum SystemMessage {
 }
 
 pub struct System {
-    channel: Receiver<SystemMessage>,
-    spatial_os: Sender<SpatialOSMsg>,
+    spatial_os: Mailbox<SpatialOSMsg>,
+    database: Mailbox<DatabaseMsg>,
 
     healths: Arc<Healths>,
 }
 
-impl System {
-    pub fn init(
-        capacity: usize,
-        spatial_os: Sender<SpatialOSMsg>,
-    ) -> (JoinHandle<()>, Sender<SystemMessage>, Arc<Healths>) {
-        let (tx, channel) = mpsc::channel();
+impl systems::System for System {
+    type Message = SystemMessage;
 
-        let mut system = Self {
-            channel,
-            spatial_os,
+    const NAME: &'static str = "damageables";
 
-            healths: Arc::new(Healths::init(capacity)),
-        };
+    fn register(registry: &mut Registry) {
+        let entities = registry.get::<Arc<Entities>>();
 
-        let arc = Arc::clone(&system.healths);
+        registry.insert(Arc::new(Healths::init(entities)));
+    }
 
-        let handle = thread::spawn(move || {
-            system.update_loop();
-        });
+    fn init(registry: &Registry) -> Self {
+        Self {
+            spatial_os: registry.get::<Mailbox<SpatialOSMsg>>(),
+            database: registry.get::<Mailbox<DatabaseMsg>>(),
 
-        (handle, tx, arc)
+            healths: registry.get::<Arc<Healths>>(),
+        }
     }
 
-    fn update_loop(&mut self) {
-        while let Ok(result) = self.channel.recv() {
-            match result {
-                SystemMessage::AddComponent(id, data) => self.healths.add(&id, data),
-                SystemMessage::RemoveComponent(id) => self.healths.remove(&id),
-                SystemMessage::TakeDamageCommand(id, data) => self.take_damage(&id, data),
-            }
+    fn handle_message(&mut self, message: SystemMessage) {
+        match message {
+            SystemMessage::AddComponent(id, data) => self.add_health(&id, data),
+            SystemMessage::RemoveComponent(id) => self.healths.remove(&id),
+            SystemMessage::TakeDamageCommand(id, data) => self.take_damage(&id, data),
         }
     }
+}
 
-    fn take_damage(&mut self, ship_id: &Ship, damage: NonZeroU32) {
-        let mut hash_map = self.healths.data.write().expect("Lock poisoned");
+impl System {
+    fn add_health(&mut self, ship_id: &Ship, health: NonZeroU32) {
+        self.healths.add(ship_id, health);
 
-        let hp = match hash_map.get_mut(ship_id) {
-            Some(hp) => *hp,
+        let message = DatabaseMsg::UpdateHealth(*ship_id, health);
+
+        self.database.send(message);
+    }
+
+    fn take_damage(&mut self, ship_id: &Ship, damage: NonZeroU32) {
+        let hp = match self.healths.read(ship_id) {
+            Some(hp) => hp,
             None => return,
         };
 
         if let Some(result) = hp.get().checked_sub(damage.get()) {
             if let Some(result) = NonZeroU32::new(result) {
-                hash_map.insert(*ship_id, result);
+                self.add_health(ship_id, result);
 
                 let message = SpatialOSMsg::UpdateComponent(
                     *ship_id,
                     UpdateComponent::Damageable(result.get()),
                 );
 
-                self.spatial_os
-                    .send(message)
-                    .expect("SpatialOS connexion terminated");
+                self.spatial_os.send(message);
 
                 return;
             }
         }
 
-        hash_map.remove(ship_id);
+        self.healths.remove(ship_id);
 
         let message = SpatialOSMsg::Delete(*ship_id);
 
-        self.spatial_os
-            .send(message)
-            .expect("SpatialOS connexion terminated");
+        self.spatial_os.send(message);
     }
 }
 
 pub struct Healths {
-    data: RwLock<HashMap<Ship, NonZeroU32>>,
+    data: Storage<NonZeroU32>,
 }
 
 impl Healths {
-    fn init(capacity: usize) -> Self {
+    fn init(entities: Arc<Entities>) -> Self {
         Self {
-            data: RwLock::new(HashMap::with_capacity(capacity)),
+            data: Storage::new(entities),
         }
     }
 
     fn add(&self, ship_id: &Ship, health: NonZeroU32) {
-        let mut hash_map = self.data.write().expect("Lock poisoned");
-
-        hash_map.insert(*ship_id, health);
+        self.data.insert(ship_id, health);
     }
 
     fn remove(&self, ship_id: &Ship) {
-        let mut hash_map = self.data.write().expect("Lock poisoned");
-
-        hash_map.remove(ship_id);
+        self.data.remove(ship_id);
     }
 
     pub fn read(&self, ship_id: &Ship) -> Option<NonZeroU32> {
-        let hash_map = self.data.read().expect("lock poisoned");
-
-        match hash_map.get(ship_id) {
-            Some(health) => Some(*health),
-            None => None,
-        }
+        self.data.get(ship_id)
     }
 }
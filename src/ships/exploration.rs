@@ -1,10 +1,11 @@
+use crate::database::SystemMessage as DatabaseMsg;
+use crate::ecs::storage::Storage;
+use crate::ecs::Entities;
+use crate::systems;
+use crate::systems::mailbox::Mailbox;
+use crate::systems::registry::Registry;
 use procedural_generation::id_types::{Asteroid, Ship};
-use std::collections::HashMap;
-use std::sync::mpsc;
-use std::sync::mpsc::{Receiver, Sender};
-use std::sync::{Arc, RwLock};
-use std::thread;
-use std::thread::JoinHandle;
+use std::sync::Arc;
 
 pub enum SystemMessage {
     AddComponent(Ship, Asteroid),
@@ -13,75 +14,69 @@ pub enum SystemMessage {
 }
 
 pub struct System {
-    channel: Receiver<SystemMessage>,
+    database: Mailbox<DatabaseMsg>,
 
     asteroids: Arc<Asteroids>,
 }
 
-impl System {
-    pub fn init(capacity: usize) -> (JoinHandle<()>, Sender<SystemMessage>, Arc<Asteroids>) {
-        let (tx, rx) = mpsc::channel();
+impl systems::System for System {
+    type Message = SystemMessage;
 
-        let mut system = Self {
-            channel: rx,
+    const NAME: &'static str = "exploration";
 
-            asteroids: Arc::new(Asteroids::init(capacity)),
-        };
+    fn register(registry: &mut Registry) {
+        let entities = registry.get::<Arc<Entities>>();
 
-        let arc = Arc::clone(&system.asteroids);
+        registry.insert(Arc::new(Asteroids::init(entities)));
+    }
 
-        let handle = thread::spawn(move || {
-            system.update_loop();
-        });
+    fn init(registry: &Registry) -> Self {
+        Self {
+            database: registry.get::<Mailbox<DatabaseMsg>>(),
 
-        (handle, tx, arc)
+            asteroids: registry.get::<Arc<Asteroids>>(),
+        }
     }
 
-    fn update_loop(&mut self) {
-        while let Ok(result) = self.channel.recv() {
-            match result {
-                SystemMessage::AddComponent(id, data) => self.asteroids.add(&id, &data),
-                SystemMessage::UpdateComponent(id, data) => self.asteroids.add(&id, &data),
-                SystemMessage::RemoveComponent(id) => self.asteroids.remove(&id),
-            }
+    fn handle_message(&mut self, message: SystemMessage) {
+        match message {
+            SystemMessage::AddComponent(id, data) => self.add_asteroid(&id, &data),
+            SystemMessage::UpdateComponent(id, data) => self.add_asteroid(&id, &data),
+            SystemMessage::RemoveComponent(id) => self.asteroids.remove(&id),
         }
     }
 }
 
+impl System {
+    fn add_asteroid(&mut self, ship_id: &Ship, asteroid_id: &Asteroid) {
+        self.asteroids.add(ship_id, asteroid_id);
+
+        let message = DatabaseMsg::UpdateAsteroid(*ship_id, *asteroid_id);
+
+        self.database.send(message);
+    }
+}
+
 pub struct Asteroids {
-    data: RwLock<HashMap<Ship, Asteroid>>,
+    data: Storage<Asteroid>,
 }
 
 impl Asteroids {
-    fn init(capacity: usize) -> Self {
+    fn init(entities: Arc<Entities>) -> Self {
         Self {
-            data: RwLock::new(HashMap::with_capacity(capacity)),
+            data: Storage::new(entities),
         }
     }
 
     fn add(&self, ship_id: &Ship, asteroid: &Asteroid) {
-        if let Ok(mut hash_map) = self.data.write() {
-            hash_map.insert(*ship_id, *asteroid);
-        }
+        self.data.insert(ship_id, *asteroid);
     }
 
     fn remove(&self, ship_id: &Ship) {
-        if let Ok(mut hash_map) = self.data.write() {
-            hash_map.remove(ship_id);
-        }
+        self.data.remove(ship_id);
     }
 
     pub fn read(&self, ship_id: &Ship) -> Option<Asteroid> {
-        let asteroid = self.data.read();
-
-        let asteroid = match asteroid {
-            Ok(asteroid) => asteroid,
-            Err(_) => return None,
-        };
-
-        match asteroid.get(ship_id) {
-            Some(asteroid) => Some(*asteroid),
-            None => None,
-        }
+        self.data.get(ship_id)
     }
 }
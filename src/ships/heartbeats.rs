@@ -1,25 +1,24 @@
 use crate::spatial_os::connexion::{CommandRequest, SystemMessage as Message};
+use crate::systems;
+use crate::systems::config::Config;
+use crate::systems::mailbox::Mailbox;
+use crate::systems::registry::Registry;
 use procedural_generation::id_types::Ship;
 use rand::Rng;
 use rand::SeedableRng;
 use rand_xoshiro::Xoshiro128StarStar;
 use std::collections::HashMap;
-use std::sync::mpsc;
-use std::sync::mpsc::{Receiver, Sender};
-use std::thread;
-use std::thread::JoinHandle;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 pub enum SystemMessage {
     AddComponent(Ship),
     RemoveComponent(Ship),
-    HeartbeatResponse(Ship),
+    HeartbeatResponse(Ship, bool),
     HeartbeatIntervalUpdate(u16),
 }
 
 pub struct System {
-    channel: Receiver<SystemMessage>,
-    connexion: Sender<Message>,
+    connexion: Mailbox<Message>,
 
     prng: Xoshiro128StarStar,
 
@@ -30,66 +29,53 @@ pub struct System {
     entities_missed_hearbeat: HashMap<Ship, u8>,
 }
 
-impl System {
-    pub fn init(
-        capacity: usize,
-        interval: u16,
-        connexion: Sender<Message>,
-    ) -> (JoinHandle<()>, Sender<SystemMessage>) {
-        let (tx, rx) = mpsc::channel();
-
-        let mut system = Self {
-            channel: rx,
-            connexion,
-
-            prng: Xoshiro128StarStar::from_entropy(),
-
-            heartbeat_interval: interval,
-            frame_time: Duration::from_millis(interval as u64),
+impl systems::System for System {
+    type Message = SystemMessage;
 
-            entities: Vec::with_capacity(capacity),
-            entities_missed_hearbeat: HashMap::with_capacity(capacity),
-        };
+    const NAME: &'static str = "heartbeats";
 
-        let handle = thread::spawn(move || {
-            //GameLoop
-            loop {
-                let before_frame = Instant::now();
+    fn init(registry: &Registry) -> Self {
+        let config = registry.get::<Config>();
 
-                system.update();
+        Self {
+            connexion: registry.get::<Mailbox<Message>>(),
 
-                let after_frame = Instant::now();
-
-                let frame_duration = after_frame.duration_since(before_frame);
+            prng: Xoshiro128StarStar::from_entropy(),
 
-                if let Some(delta_time) = system.frame_time.checked_sub(frame_duration) {
-                    std::thread::sleep(delta_time);
-                } else {
-                    println!(
-                        "The frame took {:?}, too long couldn't sleep",
-                        frame_duration
-                    );
-                }
-            }
-        });
+            heartbeat_interval: config.heartbeat_interval,
+            frame_time: Duration::from_millis(config.heartbeat_interval as u64),
 
-        (handle, tx)
+            entities: Vec::with_capacity(config.capacity),
+            entities_missed_hearbeat: HashMap::with_capacity(config.capacity),
+        }
     }
 
-    fn update(&mut self) {
-        while let Ok(result) = self.channel.try_recv() {
-            match result {
-                SystemMessage::AddComponent(ship_id) => self.add_component(ship_id),
-                SystemMessage::RemoveComponent(ship_id) => self.remove_component(ship_id),
-                SystemMessage::HeartbeatResponse(ship_id) => self.process_response(ship_id),
-                SystemMessage::HeartbeatIntervalUpdate(new_intv) => self.interval_update(new_intv),
+    fn handle_message(&mut self, message: SystemMessage) {
+        match message {
+            SystemMessage::AddComponent(ship_id) => self.add_component(ship_id),
+            SystemMessage::RemoveComponent(ship_id) => self.remove_component(ship_id),
+            SystemMessage::HeartbeatResponse(ship_id, success) => {
+                self.process_response(ship_id, success)
             }
+            SystemMessage::HeartbeatIntervalUpdate(new_intv) => self.interval_update(new_intv),
         }
+    }
 
+    fn frame_time(&self) -> Option<Duration> {
+        Some(self.frame_time)
+    }
+
+    fn tick(&mut self) {
         self.periodic_hearbeat();
     }
+}
 
+impl System {
     fn add_component(&mut self, ship_id: Ship) {
+        if self.entities.contains(&ship_id) {
+            return;
+        }
+
         self.entities.push(ship_id);
 
         self.calculate_frame_rate();
@@ -108,8 +94,11 @@ impl System {
         self.calculate_frame_rate();
     }
 
-    fn process_response(&mut self, ship_id: Ship /*, response: ResponseOP*/) {
-        //TODO if response != success
+    fn process_response(&mut self, ship_id: Ship, success: bool) {
+        if success {
+            self.entities_missed_hearbeat.remove(&ship_id);
+            return;
+        }
 
         if let Some(missed_heartbeat) = self.entities_missed_hearbeat.get_mut(&ship_id) {
             *missed_heartbeat += 1;
@@ -117,9 +106,7 @@ impl System {
             if *missed_heartbeat > 5 {
                 let message = Message::Delete(ship_id);
 
-                self.connexion
-                    .send(message)
-                    .expect("SpatialOS connexion terminated");
+                self.connexion.send(message);
             }
         } else {
             self.entities_missed_hearbeat.insert(ship_id, 1);
@@ -143,9 +130,7 @@ impl System {
 
         let message = Message::CommandRequest(CommandRequest::Heartbeat(ship_id));
 
-        self.connexion
-            .send(message)
-            .expect("SpatialOS connexion terminated");
+        self.connexion.send(message);
     }
 
     fn calculate_frame_rate(&mut self) {
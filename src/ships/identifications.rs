@@ -1,10 +1,11 @@
+use crate::database::SystemMessage as DatabaseMsg;
+use crate::ecs::storage::Storage;
+use crate::ecs::Entities;
+use crate::systems;
+use crate::systems::mailbox::Mailbox;
+use crate::systems::registry::Registry;
 use procedural_generation::id_types::{Ship, User};
-use std::collections::HashMap;
-use std::sync::mpsc;
-use std::sync::mpsc::{Receiver, Sender};
-use std::sync::{Arc, RwLock};
-use std::thread;
-use std::thread::JoinHandle;
+use std::sync::Arc;
 
 pub enum SystemMessage {
     AddComponent(Ship, User),
@@ -13,75 +14,69 @@ pub enum SystemMessage {
 }
 
 pub struct System {
-    channel: Receiver<SystemMessage>,
+    database: Mailbox<DatabaseMsg>,
 
     identifiers: Arc<Identifiers>,
 }
 
-impl System {
-    pub fn init(capacity: usize) -> (JoinHandle<()>, Sender<SystemMessage>, Arc<Identifiers>) {
-        let (tx, rx) = mpsc::channel();
+impl systems::System for System {
+    type Message = SystemMessage;
 
-        let mut system = Self {
-            channel: rx,
+    const NAME: &'static str = "identifications";
 
-            identifiers: Arc::new(Identifiers::init(capacity)),
-        };
+    fn register(registry: &mut Registry) {
+        let entities = registry.get::<Arc<Entities>>();
 
-        let arc = Arc::clone(&system.identifiers);
+        registry.insert(Arc::new(Identifiers::init(entities)));
+    }
 
-        let handle = thread::spawn(move || {
-            system.update_loop();
-        });
+    fn init(registry: &Registry) -> Self {
+        Self {
+            database: registry.get::<Mailbox<DatabaseMsg>>(),
 
-        (handle, tx, arc)
+            identifiers: registry.get::<Arc<Identifiers>>(),
+        }
     }
 
-    fn update_loop(&mut self) {
-        while let Ok(result) = self.channel.recv() {
-            match result {
-                SystemMessage::AddComponent(id, data) => self.identifiers.add(&id, &data),
-                SystemMessage::UpdateComponent(id, data) => self.identifiers.add(&id, &data),
-                SystemMessage::RemoveComponent(id) => self.identifiers.remove(&id),
-            }
+    fn handle_message(&mut self, message: SystemMessage) {
+        match message {
+            SystemMessage::AddComponent(id, data) => self.add_owner(&id, &data),
+            SystemMessage::UpdateComponent(id, data) => self.add_owner(&id, &data),
+            SystemMessage::RemoveComponent(id) => self.identifiers.remove(&id),
         }
     }
 }
 
+impl System {
+    fn add_owner(&mut self, ship_id: &Ship, user_id: &User) {
+        self.identifiers.add(ship_id, user_id);
+
+        let message = DatabaseMsg::UpdateOwner(*ship_id, *user_id);
+
+        self.database.send(message);
+    }
+}
+
 pub struct Identifiers {
-    data: RwLock<HashMap<Ship, User>>,
+    data: Storage<User>,
 }
 
 impl Identifiers {
-    fn init(capacity: usize) -> Self {
+    fn init(entities: Arc<Entities>) -> Self {
         Self {
-            data: RwLock::new(HashMap::with_capacity(capacity)),
+            data: Storage::new(entities),
         }
     }
 
     fn add(&self, ship_id: &Ship, asteroid: &User) {
-        if let Ok(mut hash_map) = self.data.write() {
-            hash_map.insert(*ship_id, *asteroid);
-        }
+        self.data.insert(ship_id, *asteroid);
     }
 
     fn remove(&self, ship_id: &Ship) {
-        if let Ok(mut hash_map) = self.data.write() {
-            hash_map.remove(ship_id);
-        }
+        self.data.remove(ship_id);
     }
 
     pub fn read(&self, ship_id: &Ship) -> Option<User> {
-        let user = self.data.read();
-
-        let user = match user {
-            Ok(user) => user,
-            Err(_) => return None,
-        };
-
-        match user.get(ship_id) {
-            Some(user) => Some(*user),
-            None => None,
-        }
+        self.data.get(ship_id)
     }
 }
@@ -1,15 +1,19 @@
-use crate::database::firestore::SystemMessage as DatabaseMsg;
+use crate::database::SystemMessage as DatabaseMsg;
+use crate::ecs::storage::Storage;
+use crate::ecs::Entities;
 use crate::spatial_os::connexion::{CommandRequest, SystemMessage as SpatialOSMsg};
+use crate::systems;
+use crate::systems::config::Config;
+use crate::systems::mailbox::{Mailbox, Overflow};
+use crate::systems::registry::Registry;
 use nalgebra::{Point2, Point3};
 use procedural_generation::id_types::Ship;
 use procedural_generation::world::asteroids::grid_cell_from_position;
 use std::collections::{HashMap, HashSet};
-use std::sync::mpsc;
-use std::sync::mpsc::{Receiver, Sender};
-use std::sync::{Arc, RwLock};
-use std::thread;
-use std::thread::JoinHandle;
-use std::time::Instant;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const SAVE_RESOLUTION: Duration = Duration::from_secs(1);
 
 pub enum SystemMessage {
     AddComponent(Ship, Point3<f64>),
@@ -19,145 +23,291 @@ pub enum SystemMessage {
 }
 
 pub struct System {
-    channel: Receiver<SystemMessage>,
-    spatial_os: Sender<SpatialOSMsg>,
-    database: Sender<DatabaseMsg>,
+    spatial_os: Mailbox<SpatialOSMsg>,
+    database: Mailbox<DatabaseMsg>,
 
     interval: u32,
 
     positions: Arc<Positions>,
-    grid_cells: HashSet<Point2<i16>>,
+    //Number of ships in each requested grid cell
+    grid_cells: HashMap<Point2<i16>, usize>,
+    ship_cells: HashMap<Ship, Point2<i16>>,
 
-    last_update: HashMap<Ship, Instant>,
+    //Ships moved since their last save, saved at most once per interval
+    dirty: HashSet<Ship>,
+    last_save: HashMap<Ship, Instant>,
 }
 
-impl System {
-    pub fn init(
-        capacity: usize,
-        interval: u32,
-        spatial_os: Sender<SpatialOSMsg>,
-        database: Sender<DatabaseMsg>,
-    ) -> (JoinHandle<()>, Sender<SystemMessage>, Arc<Positions>) {
-        let (tx, rx) = mpsc::channel();
+impl systems::System for System {
+    type Message = SystemMessage;
 
-        let mut system = Self {
-            channel: rx,
-            spatial_os,
-            database,
+    const NAME: &'static str = "positions";
 
-            interval,
+    fn register(registry: &mut Registry) {
+        let entities = registry.get::<Arc<Entities>>();
 
-            positions: Arc::new(Positions::init(capacity)),
-            grid_cells: HashSet::with_capacity(capacity),
+        registry.insert(Arc::new(Positions::init(entities)));
+    }
 
-            last_update: HashMap::with_capacity(capacity),
-        };
+    fn init(registry: &Registry) -> Self {
+        let config = registry.get::<Config>();
+        let positions = registry.get::<Arc<Positions>>();
+
+        //What moved before a restart is unknown, every ship is saved once more
+        let mut dirty = HashSet::with_capacity(config.capacity);
+        let mut last_save = HashMap::with_capacity(config.capacity);
+        let now = Instant::now();
+
+        //Cells of the ships already there were requested before the restart
+        let mut grid_cells = HashMap::with_capacity(config.capacity);
+        let mut ship_cells = HashMap::with_capacity(config.capacity);
+
+        positions.data.for_each(|ship_id, position| {
+            dirty.insert(*ship_id);
+            last_save.insert(*ship_id, now);
 
-        let arc = Arc::clone(&system.positions);
+            let grid_cell = grid_cell_from_position(*position);
 
-        let handle = thread::spawn(move || {
-            system.update_loop();
+            *grid_cells.entry(grid_cell).or_insert(0) += 1;
+            ship_cells.insert(*ship_id, grid_cell);
         });
 
-        (handle, tx, arc)
+        Self {
+            spatial_os: registry.get::<Mailbox<SpatialOSMsg>>(),
+            database: registry.get::<Mailbox<DatabaseMsg>>(),
+
+            interval: config.position_interval,
+
+            positions,
+            grid_cells,
+            ship_cells,
+
+            dirty,
+            last_save,
+        }
     }
 
-    fn update_loop(&mut self) {
-        while let Ok(result) = self.channel.recv() {
-            match result {
-                SystemMessage::AddComponent(ship_id, position) => {
-                    self.add_component(&ship_id, &position)
-                }
+    //Only the latest position of a ship matters
+    fn overflow() -> Overflow<SystemMessage> {
+        Overflow::Coalesce(|queued, message| match (queued, message) {
+            (
+                SystemMessage::UpdateComponent(queued_id, _),
+                SystemMessage::UpdateComponent(ship_id, _),
+            ) => queued_id == ship_id,
+            _ => false,
+        })
+    }
+
+    fn handle_message(&mut self, message: SystemMessage) {
+        match message {
+            SystemMessage::AddComponent(ship_id, position) => {
+                self.add_component(&ship_id, &position)
+            }
+            SystemMessage::UpdateComponent(ship_id, position) => {
+                self.update_component(&ship_id, &position)
+            }
+            SystemMessage::RemoveComponent(ship_id) => self.remove_component(&ship_id),
+            SystemMessage::PositionIntervalUpdate(new_intv) => self.interval = new_intv,
+        }
+    }
+
+    //Only the latest position of each ship in the batch is applied
+    fn handle_batch(&mut self, messages: Vec<SystemMessage>) {
+        let mut updates = HashMap::with_capacity(messages.len());
+
+        for message in messages {
+            match message {
                 SystemMessage::UpdateComponent(ship_id, position) => {
-                    self.update_component(&ship_id, &position)
+                    updates.insert(ship_id, position);
                 }
-                SystemMessage::RemoveComponent(ship_id) => self.remove_component(&ship_id),
-                SystemMessage::PositionIntervalUpdate(new_intv) => self.interval = new_intv,
+                SystemMessage::AddComponent(ship_id, _)
+                | SystemMessage::RemoveComponent(ship_id) => {
+                    //Updates sent before are superseded
+                    updates.remove(&ship_id);
+
+                    self.handle_message(message);
+                }
+                SystemMessage::PositionIntervalUpdate(_) => self.handle_message(message),
             }
         }
+
+        self.update_components(updates);
     }
 
+    fn frame_time(&self) -> Option<Duration> {
+        Some(SAVE_RESOLUTION)
+    }
+
+    fn tick(&mut self) {
+        self.save_due();
+    }
+
+    fn shutdown(&mut self) {
+        let dirty = self.dirty.iter().copied().collect::<Vec<Ship>>();
+
+        for ship_id in dirty {
+            self.save(&ship_id);
+        }
+    }
+}
+
+impl System {
     fn add_component(&mut self, ship_id: &Ship, position: &Point3<f64>) {
         self.positions.add(ship_id, position);
 
-        self.update_grid_cells(position);
+        //Comes from SpatialOS, it may be newer than the saved one
+        self.moved(ship_id, position);
 
-        self.last_update.insert(*ship_id, Instant::now());
+        //Added again on resync, the ship keeps its save schedule
+        self.last_save.entry(*ship_id).or_insert_with(Instant::now);
     }
 
     fn update_component(&mut self, ship_id: &Ship, position: &Point3<f64>) {
         self.positions.add(ship_id, position);
 
-        let now = Instant::now();
-        let before = self
-            .last_update
-            .get(&ship_id)
-            .expect("Position, last_update or grid_cell desync!");
+        self.moved(ship_id, position);
+    }
+
+    fn update_components(&mut self, updates: HashMap<Ship, Point3<f64>>) {
+        if updates.is_empty() {
+            return;
+        }
 
-        if now.duration_since(*before).as_millis() > self.interval as u128 {
-            let message = DatabaseMsg::UpdatePosition(*ship_id, *position);
+        self.positions.add_batch(updates.iter());
 
-            self.database
-                .send(message)
-                .expect("Database connexion terminated");
+        for (ship_id, position) in updates.iter() {
+            self.moved(ship_id, position);
         }
+    }
+
+    fn moved(&mut self, ship_id: &Ship, position: &Point3<f64>) {
+        self.dirty.insert(*ship_id);
 
-        self.update_grid_cells(position);
+        self.update_grid_cell(ship_id, position);
     }
 
     fn remove_component(&mut self, ship_id: &Ship) {
+        //The last position is saved even if the interval didn't elapse
+        if self.dirty.contains(ship_id) {
+            self.save(ship_id);
+        }
+
         self.positions.remove(ship_id);
 
-        self.last_update.remove(ship_id);
+        self.last_save.remove(ship_id);
+
+        if let Some(grid_cell) = self.ship_cells.remove(ship_id) {
+            self.leave_grid_cell(&grid_cell);
+        }
+    }
+
+    //Ships that stopped moving are saved too, once their interval elapsed
+    fn save_due(&mut self) {
+        let now = Instant::now();
+        let interval = Duration::from_millis(self.interval as u64);
+
+        let last_save = &self.last_save;
+
+        let due = self
+            .dirty
+            .iter()
+            .filter(|ship_id| match last_save.get(ship_id) {
+                Some(saved) => now.duration_since(*saved) >= interval,
+                None => true,
+            })
+            .copied()
+            .collect::<Vec<Ship>>();
+
+        for ship_id in due {
+            self.save(&ship_id);
+        }
     }
 
-    fn update_grid_cells(&mut self, position: &Point3<f64>) {
+    fn save(&mut self, ship_id: &Ship) {
+        self.dirty.remove(ship_id);
+
+        let position = match self.positions.read(ship_id) {
+            Some(position) => position,
+            None => return,
+        };
+
+        self.last_save.insert(*ship_id, Instant::now());
+
+        let message = DatabaseMsg::UpdatePosition(*ship_id, position);
+
+        self.database.send(message);
+    }
+
+    //The first ship entering a cell requests it, re-requested if it was released
+    fn update_grid_cell(&mut self, ship_id: &Ship, position: &Point3<f64>) {
         let grid_cell = grid_cell_from_position(*position);
 
-        if self.grid_cells.insert(grid_cell) {
+        match self.ship_cells.insert(*ship_id, grid_cell) {
+            Some(previous) if previous == grid_cell => return,
+            Some(previous) => self.leave_grid_cell(&previous),
+            None => {}
+        }
+
+        let ships = self.grid_cells.entry(grid_cell).or_insert(0);
+
+        *ships += 1;
+
+        if *ships == 1 {
             let message = SpatialOSMsg::CommandRequest(CommandRequest::GridCell(grid_cell));
 
-            self.spatial_os
-                .send(message)
-                .expect("SpatialOS connexion terminated");
+            self.spatial_os.send(message);
         }
     }
+
+    //The last ship leaving a cell releases it, its asteroids can be despawned
+    fn leave_grid_cell(&mut self, grid_cell: &Point2<i16>) {
+        let ships = match self.grid_cells.get_mut(grid_cell) {
+            Some(ships) => ships,
+            None => return,
+        };
+
+        *ships -= 1;
+
+        if *ships > 0 {
+            return;
+        }
+
+        self.grid_cells.remove(grid_cell);
+
+        let message = SpatialOSMsg::CommandRequest(CommandRequest::ReleaseGridCell(*grid_cell));
+
+        self.spatial_os.send(message);
+    }
 }
 
 pub struct Positions {
-    data: RwLock<HashMap<Ship, Point3<f64>>>,
+    data: Storage<Point3<f64>>,
 }
 
 impl Positions {
-    fn init(capacity: usize) -> Self {
+    fn init(entities: Arc<Entities>) -> Self {
         Self {
-            data: RwLock::new(HashMap::with_capacity(capacity)),
+            data: Storage::new(entities),
         }
     }
 
     fn add(&self, ship_id: &Ship, position: &Point3<f64>) {
-        if let Ok(mut hash_map) = self.data.write() {
-            hash_map.insert(*ship_id, *position);
-        }
+        self.data.insert(ship_id, *position);
+    }
+
+    fn add_batch<'a, I>(&self, positions: I)
+    where
+        I: Iterator<Item = (&'a Ship, &'a Point3<f64>)>,
+    {
+        self.data
+            .insert_batch(positions.map(|(ship_id, position)| (*ship_id, *position)));
     }
 
     fn remove(&self, ship_id: &Ship) {
-        if let Ok(mut hash_map) = self.data.write() {
-            hash_map.remove(ship_id);
-        }
+        self.data.remove(ship_id);
     }
 
     pub fn read(&self, ship_id: &Ship) -> Option<Point3<f64>> {
-        let position = self.data.read();
-
-        let position = match position {
-            Ok(position) => position,
-            Err(_) => return None,
-        };
-
-        match position.get(ship_id) {
-            Some(position) => Some(*position),
-            None => None,
-        }
+        self.data.get(ship_id)
     }
 }
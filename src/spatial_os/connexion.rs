@@ -1,68 +1,530 @@
-use crate::inventory::Inventory;
-use nalgebra::Point2;
-use procedural_generation::id_types::{Asteroid, Resource, Ship, User};
+use crate::database::ShipState;
+use crate::inventory::{CraftError, Inventory, RepairError};
+use crate::spatial_os::simulation::Simulation;
+use crate::systems;
+use crate::systems::config::Config;
+use crate::systems::mailbox::Mailbox;
+use crate::systems::registry::Registry;
+use nalgebra::{Point2, Point3};
+use procedural_generation::id_types::{Asteroid, Module, Resource, Ship, User};
 use procedural_generation::modules::samplers::SamplerStats;
 use procedural_generation::modules::scanners::ScannerStats;
-use std::sync::mpsc;
-use std::sync::mpsc::{Receiver, Sender};
-use std::thread;
-use std::thread::JoinHandle;
+use std::collections::HashMap;
+use std::fmt;
+use std::num::NonZeroU32;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+const TIMEOUT_RESOLUTION: Duration = Duration::from_millis(50);
 
 pub enum SystemMessage {
     CommandRequest(CommandRequest),
-    CommandResponse(Ship),
+    CommandResponse(Ship, ClientResponse),
     AddComponent(Ship),
     UpdateComponent(Ship, UpdateComponent),
     Log(Ship),
     Delete(Ship),
+
+    Resync,
 }
 
 pub enum CommandRequest {
     Heartbeat(Ship),
     GridCell(Point2<i16>),
-    GenerateResource(Asteroid, User, ScannerStats),
+    ReleaseGridCell(Point2<i16>),
+    GenerateResource(Asteroid, Ship, User, ScannerStats),
     ExtractResource(Asteroid, Ship, SamplerStats),
 }
 
 pub enum UpdateComponent {
-    Inventory(Inventory),
+    //Binary encoded, as the component is sent over the wire
+    Inventory(Vec<u8>),
     Scanner(Resource, u32),
     Sensor(Vec<u8>),
     Damageable(u32),
+    CraftingLevels([u8; 5]),
+}
+
+//Answers to the commands of clients
+pub enum ClientResponse {
+    //The module is queued, not crafted yet
+    CraftModule(Result<Module, CraftError>),
+    CraftProgress(Module, u8),
+    CraftCompleted(Module),
+    CancelCraft(Result<Module, CraftError>),
+    RepairModule(Result<Module, RepairError>),
+}
+
+pub enum Op {
+    AddEntity(Ship, Entity),
+    UpdateComponent(Ship, ComponentUpdate),
+    RemoveEntity(Ship),
+    CommandRequest(Ship, Command),
+    CommandResponse(CommandResponse),
+
+    //Sent by the database once the persisted state of an added ship is read
+    ShipLoaded(Ship, ShipState),
+    //Sent by the database when the persisted state of an added ship can't be decoded
+    ShipRejected(Ship),
+}
+
+#[derive(Clone, Default)]
+pub struct Entity {
+    pub identification: Option<User>,
+    pub exploration: Option<Asteroid>,
+    pub position: Option<Point3<f64>>,
+    pub heartbeat: bool,
+    pub damageable: Option<NonZeroU32>,
+    pub inventory: Option<Inventory>,
+    pub crafting_levels: Option<[u8; 5]>,
+    pub cooldowns: Vec<(Module, Duration)>,
+    pub sampler: Option<Module>,
+    pub scanner: Option<Module>,
+    pub sensor: Option<Module>,
+}
+
+pub enum ComponentUpdate {
+    Identification(User),
+    Exploration(Asteroid),
+    Position(Point3<f64>),
+    Inventory(Inventory),
+    CraftingLevels([u8; 5]),
+    Cooldown(Module, Duration),
+    Sampler(Module),
+    Scanner(Module),
+    Sensor(Module),
+}
+
+pub enum Command {
+    UseSampler,
+    UseScanner,
+    UseSensor,
+    TakeDamage(NonZeroU32),
+    CraftModule(Module, String, Vec<u8>, Vec<Resource>),
+    CancelCraft(Module),
+    RepairModule(Module),
+}
+
+pub enum CommandResponse {
+    Heartbeat(Ship, Result<(), CommandError>),
+    GridCell(Point2<i16>, Result<(), CommandError>),
+    ReleaseGridCell(Point2<i16>, Result<(), CommandError>),
+    GenerateResource(Ship, Result<(Resource, u32), CommandError>),
+    ExtractResource(Ship, Result<(Resource, u32), CommandError>),
+}
+
+pub enum CommandError {
+    NotFound,
+    Timeout,
+    ApplicationError(String),
+    InvalidResponse,
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CommandError::NotFound => write!(f, "entity not found"),
+            CommandError::Timeout => write!(f, "command timed out"),
+            CommandError::ApplicationError(message) => write!(f, "{}", message),
+            CommandError::InvalidResponse => write!(f, "response doesn't match the request"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct RequestId(u64);
+
+pub enum ResponsePayload {
+    Empty,
+    Resource(Resource, u32),
+}
+
+impl ResponsePayload {
+    fn empty(self) -> Result<(), CommandError> {
+        match self {
+            ResponsePayload::Empty => Ok(()),
+            _ => Err(CommandError::InvalidResponse),
+        }
+    }
+
+    fn resource(self) -> Result<(Resource, u32), CommandError> {
+        match self {
+            ResponsePayload::Resource(resource_id, quantity) => Ok((resource_id, quantity)),
+            _ => Err(CommandError::InvalidResponse),
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct CommandPolicy {
+    pub timeout: Duration,
+    pub retries: u8,
+    pub backoff: Duration,
+}
+
+#[derive(Copy, Clone)]
+pub struct CommandPolicies {
+    pub heartbeat: CommandPolicy,
+    pub grid_cell: CommandPolicy,
+    pub generate_resource: CommandPolicy,
+    pub extract_resource: CommandPolicy,
+}
+
+impl Default for CommandPolicies {
+    fn default() -> Self {
+        Self {
+            //Missed heartbeats are already counted by the heartbeat system
+            heartbeat: CommandPolicy {
+                timeout: Duration::from_secs(5),
+                retries: 0,
+                backoff: Duration::from_millis(0),
+            },
+            grid_cell: CommandPolicy {
+                timeout: Duration::from_secs(10),
+                retries: 5,
+                backoff: Duration::from_secs(1),
+            },
+            generate_resource: CommandPolicy {
+                timeout: Duration::from_secs(5),
+                retries: 2,
+                backoff: Duration::from_millis(500),
+            },
+            //Extraction depletes the asteroid, retrying could extract twice
+            extract_resource: CommandPolicy {
+                timeout: Duration::from_secs(5),
+                retries: 0,
+                backoff: Duration::from_millis(0),
+            },
+        }
+    }
+}
+
+impl CommandPolicies {
+    fn get(&self, request: &CommandRequest) -> &CommandPolicy {
+        match request {
+            CommandRequest::Heartbeat(_) => &self.heartbeat,
+            CommandRequest::GridCell(_) | CommandRequest::ReleaseGridCell(_) => &self.grid_cell,
+            CommandRequest::GenerateResource(..) => &self.generate_resource,
+            CommandRequest::ExtractResource(..) => &self.extract_resource,
+        }
+    }
+}
+
+struct PendingRequest {
+    request: CommandRequest,
+    attempts: u8,
+    in_flight: bool,
+    deadline: Instant,
 }
 
 pub struct System {
-    channel: Receiver<SystemMessage>,
+    ops: Mailbox<Op>,
+
+    runtime: Arc<Mutex<Simulation>>,
+    policies: CommandPolicies,
+
+    next_request_id: u64,
+    pending_requests: HashMap<RequestId, PendingRequest>,
+}
+
+impl systems::System for System {
+    type Message = SystemMessage;
+
+    const NAME: &'static str = "spatial_os";
+
+    fn init(registry: &Registry) -> Self {
+        let config = registry.get::<Config>();
+
+        Self {
+            ops: registry.get::<Mailbox<Op>>(),
+
+            runtime: registry.get::<Arc<Mutex<Simulation>>>(),
+            policies: registry.get::<CommandPolicies>(),
+
+            next_request_id: 0,
+            pending_requests: HashMap::with_capacity(config.capacity),
+        }
+    }
+
+    fn handle_message(&mut self, message: SystemMessage) {
+        match message {
+            SystemMessage::CommandRequest(req) => self.command_request(req),
+            SystemMessage::CommandResponse(ship_id, response) => {
+                lock(&self.runtime).client_response(&ship_id, response)
+            }
+            SystemMessage::AddComponent(_ship_id) => {}
+            SystemMessage::UpdateComponent(ship_id, component) => {
+                lock(&self.runtime).update_component(&ship_id, component)
+            }
+            SystemMessage::Log(_ship_id) => {}
+            SystemMessage::Delete(ship_id) => lock(&self.runtime).delete(&ship_id),
+            SystemMessage::Resync => lock(&self.runtime).resync(),
+        }
+
+        self.dispatch_ops();
+    }
+
+    fn frame_time(&self) -> Option<Duration> {
+        Some(TIMEOUT_RESOLUTION)
+    }
+
+    fn tick(&mut self) {
+        self.check_timeouts();
+
+        self.dispatch_ops();
+    }
 }
 
 impl System {
-    pub fn init() -> (JoinHandle<()>, Sender<SystemMessage>) {
-        let (tx, rx) = mpsc::channel();
-
-        let mut system = Self { channel: rx };
-
-        let handle = thread::spawn(move || {
-            system.update_loop();
-        });
-
-        (handle, tx)
-    }
-
-    fn update_loop(&mut self) {
-        while let Ok(message) = self.channel.recv() {
-            match message {
-                SystemMessage::CommandRequest(req) => match req {
-                    CommandRequest::Heartbeat(_ship_id) => {}
-                    CommandRequest::GridCell(_grid_cell) => {}
-                    CommandRequest::GenerateResource(_asteroid_id, _user_id, _scanner) => {}
-                    CommandRequest::ExtractResource(_asteroid_id, _ship_id, _sampler) => {}
-                },
-                SystemMessage::CommandResponse(_ship_id) => {}
-                SystemMessage::AddComponent(_ship_id) => {}
-                SystemMessage::UpdateComponent(_ship_id, _component) => {}
-                SystemMessage::Log(_ship_id) => {}
-                SystemMessage::Delete(_ship_id) => {}
+    fn command_request(&mut self, request: CommandRequest) {
+        let request_id = RequestId(self.next_request_id);
+
+        self.next_request_id = self.next_request_id.wrapping_add(1);
+
+        lock(&self.runtime).command_request(request_id, &request);
+
+        let pending = PendingRequest {
+            deadline: Instant::now() + self.policies.get(&request).timeout,
+            request,
+            attempts: 0,
+            in_flight: true,
+        };
+
+        self.pending_requests.insert(request_id, pending);
+    }
+
+    fn check_timeouts(&mut self) {
+        let now = Instant::now();
+
+        let expired = self
+            .pending_requests
+            .iter()
+            .filter(|(_, pending)| pending.deadline <= now)
+            .map(|(request_id, _)| *request_id)
+            .collect::<Vec<RequestId>>();
+
+        for request_id in expired {
+            let pending = match self.pending_requests.get_mut(&request_id) {
+                Some(pending) => pending,
+                None => continue,
+            };
+
+            let policy = self.policies.get(&pending.request);
+
+            if !pending.in_flight {
+                //Backoff elapsed, same request ID so a late answer is still accepted
+                lock(&self.runtime).command_request(request_id, &pending.request);
+
+                pending.in_flight = true;
+                pending.deadline = now + policy.timeout;
+            } else if pending.attempts < policy.retries {
+                pending.in_flight = false;
+                pending.deadline = now + policy.backoff * 2u32.pow(pending.attempts as u32);
+                pending.attempts += 1;
+            } else if let Some(response) =
+                self.command_response(&request_id, Err(CommandError::Timeout))
+            {
+                self.send_op(Op::CommandResponse(response));
+            }
+        }
+    }
+
+    fn command_response(
+        &mut self,
+        request_id: &RequestId,
+        result: Result<ResponsePayload, CommandError>,
+    ) -> Option<CommandResponse> {
+        //Responses to unknown or already answered requests are ignored
+        let pending = self.pending_requests.remove(request_id)?;
+
+        let response = match pending.request {
+            CommandRequest::Heartbeat(ship_id) => {
+                CommandResponse::Heartbeat(ship_id, result.and_then(ResponsePayload::empty))
             }
+            CommandRequest::GridCell(grid_cell) => {
+                CommandResponse::GridCell(grid_cell, result.and_then(ResponsePayload::empty))
+            }
+            CommandRequest::ReleaseGridCell(grid_cell) => {
+                CommandResponse::ReleaseGridCell(grid_cell, result.and_then(ResponsePayload::empty))
+            }
+            CommandRequest::GenerateResource(_asteroid_id, ship_id, _user_id, _scanner) => {
+                CommandResponse::GenerateResource(
+                    ship_id,
+                    result.and_then(ResponsePayload::resource),
+                )
+            }
+            CommandRequest::ExtractResource(_asteroid_id, ship_id, _sampler) => {
+                CommandResponse::ExtractResource(
+                    ship_id,
+                    result.and_then(ResponsePayload::resource),
+                )
+            }
+        };
+
+        Some(response)
+    }
+
+    fn dispatch_ops(&mut self) {
+        let ops = lock(&self.runtime).drain_ops().collect::<Vec<_>>();
+
+        for op in ops {
+            self.send_op(op);
         }
+
+        let responses = lock(&self.runtime).drain_responses().collect::<Vec<_>>();
+
+        for (request_id, result) in responses {
+            if let Some(response) = self.command_response(&request_id, result) {
+                self.send_op(Op::CommandResponse(response));
+            }
+        }
+
+        //The simulation has no clients to answer, failed commands are only logged
+        let client_responses = lock(&self.runtime)
+            .drain_client_responses()
+            .collect::<Vec<_>>();
+
+        for (_ship_id, response) in client_responses {
+            match response {
+                ClientResponse::CraftModule(Err(error))
+                | ClientResponse::CancelCraft(Err(error)) => {
+                    println!("Craft command failed: {}", error)
+                }
+                ClientResponse::RepairModule(Err(error)) => {
+                    println!("Repair command failed: {}", error)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn send_op(&self, op: Op) {
+        //Once the dispatcher is stopped, inbound ops are dropped by its closed mailbox
+        self.ops.send(op);
+    }
+}
+
+fn lock(runtime: &Mutex<Simulation>) -> MutexGuard<'_, Simulation> {
+    runtime.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::systems::mailbox::{Envelope, Overflow, Receiver};
+    use crate::systems::System as _;
+    use serde_json::json;
+
+    fn connexion(runtime: &Arc<Mutex<Simulation>>) -> (System, Receiver<Op>) {
+        let ops = Mailbox::new(16, Overflow::Block);
+        let receiver = ops.receiver();
+
+        let system = System {
+            ops,
+
+            runtime: runtime.clone(),
+            policies: CommandPolicies::default(),
+
+            next_request_id: 0,
+            pending_requests: HashMap::new(),
+        };
+
+        (system, receiver)
+    }
+
+    fn received(receiver: &Receiver<Op>) -> Vec<Op> {
+        let mut ops = Vec::new();
+
+        while let Some(Envelope::Message(op)) = receiver.try_recv() {
+            ops.push(op);
+        }
+
+        ops
+    }
+
+    fn resource(op: Op) -> Result<(Resource, u32), CommandError> {
+        match op {
+            Op::CommandResponse(CommandResponse::GenerateResource(_, result))
+            | Op::CommandResponse(CommandResponse::ExtractResource(_, result)) => result,
+            _ => panic!("Expected a resource response"),
+        }
+    }
+
+    #[test]
+    fn seeded_ships_are_added_and_answer_heartbeats() {
+        let runtime = Arc::new(Mutex::new(Simulation::new(4)));
+        let (mut system, receiver) = connexion(&runtime);
+        let ship_id = serde_json::from_value::<Ship>(json!(1)).expect("Invalid ship id");
+        let unknown_id = serde_json::from_value::<Ship>(json!(2)).expect("Invalid ship id");
+
+        lock(&runtime).add_entity(&ship_id, Entity::default());
+
+        system.handle_message(SystemMessage::CommandRequest(CommandRequest::Heartbeat(
+            ship_id,
+        )));
+        system.handle_message(SystemMessage::CommandRequest(CommandRequest::Heartbeat(
+            unknown_id,
+        )));
+
+        let mut ops = received(&receiver).into_iter();
+
+        assert!(matches!(ops.next(), Some(Op::AddEntity(added, _)) if added == ship_id));
+        assert!(matches!(
+            ops.next(),
+            Some(Op::CommandResponse(CommandResponse::Heartbeat(answered, Ok(()))))
+                if answered == ship_id
+        ));
+        assert!(matches!(
+            ops.next(),
+            Some(Op::CommandResponse(CommandResponse::Heartbeat(answered, Err(CommandError::NotFound))))
+                if answered == unknown_id
+        ));
+        assert!(ops.next().is_none());
+        assert!(system.pending_requests.is_empty());
+    }
+
+    #[test]
+    fn seeded_deposits_are_scanned_then_extracted_until_depleted() {
+        let runtime = Arc::new(Mutex::new(Simulation::new(4)));
+        let (mut system, receiver) = connexion(&runtime);
+        let ship_id = serde_json::from_value::<Ship>(json!(1)).expect("Invalid ship id");
+        let user_id = serde_json::from_value::<User>(json!(1)).expect("Invalid user id");
+        let asteroid_id =
+            serde_json::from_value::<Asteroid>(json!(1)).expect("Invalid asteroid id");
+        let unknown_id = serde_json::from_value::<Asteroid>(json!(2)).expect("Invalid asteroid id");
+
+        let sampler =
+            || SamplerStats::from_properties(&[0; 5]).unwrap_or_else(|_| panic!("Invalid sampler"));
+        let (resource_id, _) = sampler().get_requirements()[0];
+
+        lock(&runtime).add_deposit(&asteroid_id, &resource_id, 15);
+
+        let scan = CommandRequest::GenerateResource(
+            asteroid_id,
+            ship_id,
+            user_id,
+            ScannerStats::from_properties(&[0; 5]).unwrap_or_else(|_| panic!("Invalid scanner")),
+        );
+        let extract = || CommandRequest::ExtractResource(asteroid_id, ship_id, sampler());
+
+        system.handle_message(SystemMessage::CommandRequest(scan));
+        system.handle_message(SystemMessage::CommandRequest(extract()));
+        system.handle_message(SystemMessage::CommandRequest(extract()));
+        system.handle_message(SystemMessage::CommandRequest(extract()));
+        system.handle_message(SystemMessage::CommandRequest(
+            CommandRequest::ExtractResource(unknown_id, ship_id, sampler()),
+        ));
+
+        let mut ops = received(&receiver).into_iter().map(resource);
+
+        assert!(matches!(ops.next(), Some(Ok(deposit)) if deposit == (resource_id, 15)));
+        assert!(matches!(ops.next(), Some(Ok(sample)) if sample == (resource_id, 10)));
+        assert!(matches!(ops.next(), Some(Ok(sample)) if sample == (resource_id, 5)));
+        assert!(matches!(
+            ops.next(),
+            Some(Err(CommandError::ApplicationError(_)))
+        ));
+        assert!(matches!(ops.next(), Some(Err(CommandError::NotFound))));
+        assert!(ops.next().is_none());
     }
 }
@@ -1,3 +1,353 @@
-// Connect to SpatialOS
-// Initialize all systems
-// Dispatch updates to systems
+use crate::database::{ShipState, SystemMessage as DatabaseMsg};
+use crate::inventory::crafting::SystemMessage as CraftingMsg;
+use crate::inventory::{Inventory, SystemMessage as InvMsg};
+use crate::modules::cooldowns::SystemMessage as CooldownMsg;
+use crate::modules::samplers::SystemMessage as SamplerMsg;
+use crate::modules::scanners::SystemMessage as ScannerMsg;
+use crate::modules::sensors::SystemMessage as SensorMsg;
+use crate::ships::damageables::SystemMessage as DamageableMsg;
+use crate::ships::exploration::SystemMessage as ExplorationMsg;
+use crate::ships::heartbeats::SystemMessage as HeartbeatMsg;
+use crate::ships::identifications::SystemMessage as IdMsg;
+use crate::ships::positions::SystemMessage as PositionMsg;
+use crate::spatial_os::connexion::{Command, CommandResponse, ComponentUpdate, Entity, Op};
+use crate::systems;
+use crate::systems::config::Config;
+use crate::systems::mailbox::Mailbox;
+use crate::systems::registry::Registry;
+use procedural_generation::id_types::{Module, Ship};
+use std::collections::HashMap;
+use std::time::Duration;
+
+const INVENTORY_CAPACITY: usize = 8;
+
+// Dispatch updates from SpatialOS to systems
+pub struct System {
+    database: Mailbox<DatabaseMsg>,
+    identifications: Mailbox<IdMsg>,
+    exploration: Mailbox<ExplorationMsg>,
+    positions: Mailbox<PositionMsg>,
+    heartbeats: Mailbox<HeartbeatMsg>,
+    damageables: Mailbox<DamageableMsg>,
+    inventory: Mailbox<InvMsg>,
+    crafting: Mailbox<CraftingMsg>,
+    cooldowns: Mailbox<CooldownMsg>,
+    sensors: Mailbox<SensorMsg>,
+    scanners: Mailbox<ScannerMsg>,
+    samplers: Mailbox<SamplerMsg>,
+
+    modules: HashMap<Ship, Vec<Module>>,
+
+    //Ships waiting for their persisted state, with the ops received meanwhile
+    loading: HashMap<Ship, (Entity, Vec<Op>)>,
+}
+
+impl systems::System for System {
+    type Message = Op;
+
+    const NAME: &'static str = "dispatcher";
+
+    fn init(registry: &Registry) -> Self {
+        let config = registry.get::<Config>();
+
+        Self {
+            database: registry.get::<Mailbox<DatabaseMsg>>(),
+            identifications: registry.get::<Mailbox<IdMsg>>(),
+            exploration: registry.get::<Mailbox<ExplorationMsg>>(),
+            positions: registry.get::<Mailbox<PositionMsg>>(),
+            heartbeats: registry.get::<Mailbox<HeartbeatMsg>>(),
+            damageables: registry.get::<Mailbox<DamageableMsg>>(),
+            inventory: registry.get::<Mailbox<InvMsg>>(),
+            crafting: registry.get::<Mailbox<CraftingMsg>>(),
+            cooldowns: registry.get::<Mailbox<CooldownMsg>>(),
+            sensors: registry.get::<Mailbox<SensorMsg>>(),
+            scanners: registry.get::<Mailbox<ScannerMsg>>(),
+            samplers: registry.get::<Mailbox<SamplerMsg>>(),
+
+            modules: HashMap::with_capacity(config.capacity),
+
+            loading: HashMap::with_capacity(config.capacity),
+        }
+    }
+
+    fn handle_message(&mut self, op: Op) {
+        let ship_id = match &op {
+            Op::UpdateComponent(ship_id, _) | Op::CommandRequest(ship_id, _) => Some(*ship_id),
+            _ => None,
+        };
+
+        //A ship can't be used before its state is loaded
+        if let Some(ship_id) = ship_id {
+            if let Some((_, deferred)) = self.loading.get_mut(&ship_id) {
+                deferred.push(op);
+                return;
+            }
+        }
+
+        match op {
+            Op::AddEntity(ship_id, entity) => self.load_entity(&ship_id, entity),
+            Op::UpdateComponent(ship_id, update) => self.update_component(&ship_id, update),
+            Op::RemoveEntity(ship_id) => self.remove_entity(&ship_id),
+            Op::CommandRequest(ship_id, command) => self.command_request(&ship_id, command),
+            Op::CommandResponse(response) => self.command_response(response),
+            Op::ShipLoaded(ship_id, state) => self.ship_loaded(&ship_id, state),
+            Op::ShipRejected(ship_id) => self.ship_rejected(&ship_id),
+        }
+    }
+}
+
+impl System {
+    fn load_entity(&mut self, ship_id: &Ship, entity: Entity) {
+        if let Some(loading) = self.loading.get_mut(ship_id) {
+            loading.0 = entity;
+            return;
+        }
+
+        self.loading.insert(*ship_id, (entity, Vec::new()));
+
+        self.database.send(DatabaseMsg::LoadShip(*ship_id));
+    }
+
+    fn ship_loaded(&mut self, ship_id: &Ship, state: ShipState) {
+        //Removed while loading
+        let (mut entity, deferred) = match self.loading.remove(ship_id) {
+            Some(loading) => loading,
+            None => return,
+        };
+
+        //SpatialOS has the live position, the saved one can be a whole interval old
+        entity.position = entity.position.or(state.position);
+
+        //Other persisted state takes precedence over SpatialOS
+        entity.damageable = state.health.or(entity.damageable);
+        entity.identification = state.owner.or(entity.identification);
+        entity.exploration = state.asteroid.or(entity.exploration);
+        entity.crafting_levels = state
+            .crafting_levels
+            .or(entity.crafting_levels)
+            .or(Some([0; 5]));
+
+        //Not part of the SpatialOS entity, only the worker knows it
+        if let Some(experience) = state.crafting_experience {
+            self.crafting
+                .send(CraftingMsg::LoadExperience(*ship_id, experience));
+        }
+
+        entity.inventory = state
+            .inventory
+            .or(entity.inventory)
+            .or_else(|| Some(Inventory::new(INVENTORY_CAPACITY)));
+
+        self.add_entity(ship_id, entity);
+
+        for op in deferred {
+            systems::System::handle_message(self, op);
+        }
+    }
+
+    //Kept out of every system so that nothing writes over the stored state, a resync loads it again
+    fn ship_rejected(&mut self, ship_id: &Ship) {
+        self.loading.remove(ship_id);
+    }
+
+    fn add_entity(&mut self, ship_id: &Ship, entity: Entity) {
+        if let Some(user_id) = entity.identification {
+            let message = IdMsg::AddComponent(*ship_id, user_id);
+
+            self.identifications.send(message);
+        }
+
+        if let Some(asteroid_id) = entity.exploration {
+            let message = ExplorationMsg::AddComponent(*ship_id, asteroid_id);
+
+            self.exploration.send(message);
+        }
+
+        if let Some(position) = entity.position {
+            let message = PositionMsg::AddComponent(*ship_id, position);
+
+            self.positions.send(message);
+        }
+
+        if entity.heartbeat {
+            let message = HeartbeatMsg::AddComponent(*ship_id);
+
+            self.heartbeats.send(message);
+        }
+
+        if let Some(health) = entity.damageable {
+            let message = DamageableMsg::AddComponent(*ship_id, health);
+
+            self.damageables.send(message);
+        }
+
+        if let Some(inventory) = entity.inventory {
+            let message = InvMsg::AddComponent(*ship_id, inventory);
+
+            self.inventory.send(message);
+        }
+
+        if let Some(levels) = entity.crafting_levels {
+            let message = CraftingMsg::AddComponent(*ship_id, levels);
+
+            self.crafting.send(message);
+        }
+
+        for (module_id, duration) in entity.cooldowns {
+            self.add_cooldown(ship_id, module_id, duration);
+        }
+
+        if let Some(module_id) = entity.sampler {
+            let message = SamplerMsg::AddComponent(*ship_id, module_id);
+
+            self.samplers.send(message);
+        }
+
+        if let Some(module_id) = entity.scanner {
+            let message = ScannerMsg::AddComponent(*ship_id, module_id);
+
+            self.scanners.send(message);
+        }
+
+        if let Some(module_id) = entity.sensor {
+            let message = SensorMsg::AddComponent(*ship_id, module_id);
+
+            self.sensors.send(message);
+        }
+    }
+
+    fn update_component(&mut self, ship_id: &Ship, update: ComponentUpdate) {
+        match update {
+            ComponentUpdate::Identification(user_id) => {
+                let message = IdMsg::UpdateComponent(*ship_id, user_id);
+
+                self.identifications.send(message);
+            }
+            ComponentUpdate::Exploration(asteroid_id) => {
+                let message = ExplorationMsg::UpdateComponent(*ship_id, asteroid_id);
+
+                self.exploration.send(message);
+            }
+            ComponentUpdate::Position(position) => {
+                let message = PositionMsg::UpdateComponent(*ship_id, position);
+
+                self.positions.send(message);
+            }
+            ComponentUpdate::Inventory(inventory) => {
+                let message = InvMsg::UpdateComponent(*ship_id, inventory);
+
+                self.inventory.send(message);
+            }
+            ComponentUpdate::CraftingLevels(levels) => {
+                let message = CraftingMsg::UpdateComponent(*ship_id, levels);
+
+                self.crafting.send(message);
+            }
+            ComponentUpdate::Cooldown(module_id, duration) => {
+                self.add_cooldown(ship_id, module_id, duration)
+            }
+            ComponentUpdate::Sampler(module_id) => {
+                let message = SamplerMsg::UpdateComponent(*ship_id, module_id);
+
+                self.samplers.send(message);
+            }
+            ComponentUpdate::Scanner(module_id) => {
+                let message = ScannerMsg::UpdateComponent(*ship_id, module_id);
+
+                self.scanners.send(message);
+            }
+            ComponentUpdate::Sensor(module_id) => {
+                let message = SensorMsg::UpdateComponent(*ship_id, module_id);
+
+                self.sensors.send(message);
+            }
+        }
+    }
+
+    fn remove_entity(&mut self, ship_id: &Ship) {
+        self.loading.remove(ship_id);
+
+        self.identifications.send(IdMsg::RemoveComponent(*ship_id));
+
+        self.exploration
+            .send(ExplorationMsg::RemoveComponent(*ship_id));
+
+        self.positions.send(PositionMsg::RemoveComponent(*ship_id));
+
+        self.heartbeats
+            .send(HeartbeatMsg::RemoveComponent(*ship_id));
+
+        self.damageables
+            .send(DamageableMsg::RemoveComponent(*ship_id));
+
+        self.inventory.send(InvMsg::RemoveComponent(*ship_id));
+
+        self.crafting.send(CraftingMsg::RemoveComponent(*ship_id));
+
+        if let Some(module_ids) = self.modules.remove(ship_id) {
+            for module_id in module_ids {
+                self.cooldowns.send(CooldownMsg::RemoveComponent(module_id));
+            }
+        }
+
+        self.samplers.send(SamplerMsg::RemoveComponent(*ship_id));
+
+        self.scanners.send(ScannerMsg::RemoveComponent(*ship_id));
+
+        self.sensors.send(SensorMsg::RemoveComponent(*ship_id));
+    }
+
+    fn command_request(&mut self, ship_id: &Ship, command: Command) {
+        match command {
+            Command::UseSampler => self.samplers.send(SamplerMsg::UseSampler(*ship_id)),
+            Command::UseScanner => self.scanners.send(ScannerMsg::UseScanner(*ship_id)),
+            Command::UseSensor => self.sensors.send(SensorMsg::UseSensor(*ship_id)),
+            Command::TakeDamage(damage) => self
+                .damageables
+                .send(DamageableMsg::TakeDamageCommand(*ship_id, damage)),
+            Command::CraftModule(module_id, name, properties, resources) => self.inventory.send(
+                InvMsg::CraftModule(*ship_id, module_id, name, properties, resources),
+            ),
+            Command::CancelCraft(module_id) => self
+                .inventory
+                .send(InvMsg::CancelCraft(*ship_id, module_id)),
+            Command::RepairModule(module_id) => self
+                .inventory
+                .send(InvMsg::RepairModule(*ship_id, module_id)),
+        }
+    }
+
+    fn command_response(&mut self, response: CommandResponse) {
+        match response {
+            CommandResponse::Heartbeat(ship_id, result) => {
+                let message = HeartbeatMsg::HeartbeatResponse(ship_id, result.is_ok());
+
+                self.heartbeats.send(message);
+            }
+            CommandResponse::GridCell(_grid_cell, _result) => {}
+            CommandResponse::ReleaseGridCell(_grid_cell, _result) => {}
+            CommandResponse::GenerateResource(ship_id, result) => {
+                let message = ScannerMsg::CommandResponse(ship_id, result);
+
+                self.scanners.send(message);
+            }
+            CommandResponse::ExtractResource(ship_id, result) => {
+                let message = SamplerMsg::CommandResponse(ship_id, result);
+
+                self.samplers.send(message);
+            }
+        }
+    }
+
+    fn add_cooldown(&mut self, ship_id: &Ship, module_id: Module, duration: Duration) {
+        let module_ids = self.modules.entry(*ship_id).or_default();
+
+        if !module_ids.contains(&module_id) {
+            module_ids.push(module_id);
+        }
+
+        let message = CooldownMsg::AddOrUpdateComponent(module_id, duration);
+
+        self.cooldowns.send(message);
+    }
+}
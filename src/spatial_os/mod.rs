@@ -1,2 +1,3 @@
 pub mod connexion;
 pub mod init;
+pub mod simulation;
@@ -0,0 +1,288 @@
+use crate::inventory::encoding;
+use crate::spatial_os::connexion::{
+    ClientResponse, CommandError, CommandRequest, Entity, Op, RequestId, ResponsePayload,
+    UpdateComponent,
+};
+#[cfg(test)]
+use crate::spatial_os::connexion::{Command, ComponentUpdate};
+use nalgebra::Point2;
+use procedural_generation::id_types::{Asteroid, Resource, Ship};
+use std::collections::{HashMap, HashSet};
+use std::num::NonZeroU32;
+use std::vec::Drain;
+
+const SAMPLE_QUANTITY: u32 = 10;
+
+pub struct Simulation {
+    entities: HashMap<Ship, Entity>,
+    scans: HashMap<Ship, (Resource, u32)>,
+    samples: HashMap<Ship, Vec<u8>>,
+
+    grid_cells: HashSet<Point2<i16>>,
+    deposits: HashMap<Asteroid, (Resource, u32)>,
+
+    ops: Vec<Op>,
+    responses: Vec<(RequestId, Result<ResponsePayload, CommandError>)>,
+    client_responses: Vec<(Ship, ClientResponse)>,
+}
+
+impl Simulation {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entities: HashMap::with_capacity(capacity),
+            scans: HashMap::with_capacity(capacity),
+            samples: HashMap::with_capacity(capacity),
+
+            grid_cells: HashSet::with_capacity(capacity),
+            deposits: HashMap::with_capacity(capacity),
+
+            ops: Vec::with_capacity(capacity),
+            responses: Vec::with_capacity(capacity),
+            client_responses: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub fn resync(&mut self) {
+        for (ship_id, entity) in self.entities.iter() {
+            self.ops.push(Op::AddEntity(*ship_id, entity.clone()));
+        }
+    }
+
+    pub fn drain_client_responses(&mut self) -> Drain<'_, (Ship, ClientResponse)> {
+        self.client_responses.drain(..)
+    }
+
+    pub fn command_request(&mut self, request_id: RequestId, request: &CommandRequest) {
+        let result = match request {
+            CommandRequest::Heartbeat(ship_id) => self.heartbeat(ship_id),
+            CommandRequest::GridCell(grid_cell) => {
+                self.grid_cells.insert(*grid_cell);
+
+                Ok(ResponsePayload::Empty)
+            }
+            CommandRequest::ReleaseGridCell(grid_cell) => {
+                self.grid_cells.remove(grid_cell);
+
+                Ok(ResponsePayload::Empty)
+            }
+            CommandRequest::GenerateResource(asteroid_id, _ship_id, _user_id, _scanner) => {
+                self.generate_resource(asteroid_id)
+            }
+            CommandRequest::ExtractResource(asteroid_id, _ship_id, _sampler) => {
+                self.extract_resource(asteroid_id)
+            }
+        };
+
+        self.responses.push((request_id, result));
+    }
+
+    pub fn client_response(&mut self, ship_id: &Ship, response: ClientResponse) {
+        if !self.entities.contains_key(ship_id) {
+            return;
+        }
+
+        self.client_responses.push((*ship_id, response));
+    }
+
+    pub fn update_component(&mut self, ship_id: &Ship, update: UpdateComponent) {
+        let entity = match self.entities.get_mut(ship_id) {
+            Some(entity) => entity,
+            None => return,
+        };
+
+        match update {
+            UpdateComponent::Inventory(bytes) => match encoding::from_bytes(&bytes) {
+                Ok(inventory) => entity.inventory = Some(inventory),
+                Err(error) => println!("Invalid inventory update: {}", error),
+            },
+            UpdateComponent::Scanner(resource_id, quantity) => {
+                self.scans.insert(*ship_id, (resource_id, quantity));
+            }
+            UpdateComponent::Sensor(samples) => {
+                self.samples.insert(*ship_id, samples);
+            }
+            UpdateComponent::Damageable(health) => entity.damageable = NonZeroU32::new(health),
+            UpdateComponent::CraftingLevels(levels) => entity.crafting_levels = Some(levels),
+        }
+    }
+
+    pub fn delete(&mut self, ship_id: &Ship) {
+        if self.entities.remove(ship_id).is_none() {
+            return;
+        }
+
+        self.scans.remove(ship_id);
+        self.samples.remove(ship_id);
+
+        self.ops.push(Op::RemoveEntity(*ship_id));
+    }
+
+    pub fn drain_ops(&mut self) -> Drain<'_, Op> {
+        self.ops.drain(..)
+    }
+
+    pub fn drain_responses(
+        &mut self,
+    ) -> Drain<'_, (RequestId, Result<ResponsePayload, CommandError>)> {
+        self.responses.drain(..)
+    }
+
+    fn heartbeat(&self, ship_id: &Ship) -> Result<ResponsePayload, CommandError> {
+        if self.entities.contains_key(ship_id) {
+            Ok(ResponsePayload::Empty)
+        } else {
+            Err(CommandError::NotFound)
+        }
+    }
+
+    fn generate_resource(&self, asteroid_id: &Asteroid) -> Result<ResponsePayload, CommandError> {
+        match self.deposits.get(asteroid_id) {
+            Some((resource_id, quantity)) => Ok(ResponsePayload::Resource(*resource_id, *quantity)),
+            None => Err(CommandError::NotFound),
+        }
+    }
+
+    fn extract_resource(
+        &mut self,
+        asteroid_id: &Asteroid,
+    ) -> Result<ResponsePayload, CommandError> {
+        let (resource_id, remaining) = match self.deposits.get_mut(asteroid_id) {
+            Some(deposit) => deposit,
+            None => return Err(CommandError::NotFound),
+        };
+
+        if *remaining == 0 {
+            return Err(CommandError::ApplicationError(String::from(
+                "Asteroid depleted",
+            )));
+        }
+
+        let quantity = SAMPLE_QUANTITY.min(*remaining);
+
+        *remaining -= quantity;
+
+        Ok(ResponsePayload::Resource(*resource_id, quantity))
+    }
+}
+
+//Ships and clients are seeded by the tests, the binary only answers the workers
+#[cfg(test)]
+impl Simulation {
+    pub fn add_entity(&mut self, ship_id: &Ship, entity: Entity) {
+        self.entities.insert(*ship_id, entity.clone());
+
+        self.ops.push(Op::AddEntity(*ship_id, entity));
+    }
+
+    pub fn client_update(&mut self, ship_id: &Ship, update: ComponentUpdate) {
+        let entity = match self.entities.get_mut(ship_id) {
+            Some(entity) => entity,
+            None => return,
+        };
+
+        match &update {
+            ComponentUpdate::Identification(user_id) => entity.identification = Some(*user_id),
+            ComponentUpdate::Exploration(asteroid_id) => entity.exploration = Some(*asteroid_id),
+            ComponentUpdate::Position(position) => entity.position = Some(*position),
+            ComponentUpdate::Inventory(inventory) => entity.inventory = Some(inventory.clone()),
+            ComponentUpdate::CraftingLevels(levels) => entity.crafting_levels = Some(*levels),
+            ComponentUpdate::Cooldown(module_id, duration) => {
+                entity.cooldowns.retain(|(id, _)| id != module_id);
+                entity.cooldowns.push((*module_id, *duration));
+            }
+            ComponentUpdate::Sampler(module_id) => entity.sampler = Some(*module_id),
+            ComponentUpdate::Scanner(module_id) => entity.scanner = Some(*module_id),
+            ComponentUpdate::Sensor(module_id) => entity.sensor = Some(*module_id),
+        }
+
+        self.ops.push(Op::UpdateComponent(*ship_id, update));
+    }
+
+    pub fn client_command(&mut self, ship_id: &Ship, command: Command) {
+        if !self.entities.contains_key(ship_id) {
+            return;
+        }
+
+        self.ops.push(Op::CommandRequest(*ship_id, command));
+    }
+
+    pub fn add_deposit(&mut self, asteroid_id: &Asteroid, resource_id: &Resource, quantity: u32) {
+        self.deposits.insert(*asteroid_id, (*resource_id, quantity));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Point3;
+    use procedural_generation::id_types::Module;
+    use serde_json::json;
+
+    #[test]
+    fn client_updates_and_commands_only_reach_seeded_ships() {
+        let mut simulation = Simulation::new(4);
+        let position = Point3::new(1.0, 2.0, 3.0);
+        let ship_id = serde_json::from_value::<Ship>(json!(1)).expect("Invalid ship id");
+        let unknown_id = serde_json::from_value::<Ship>(json!(2)).expect("Invalid ship id");
+
+        simulation.add_entity(&ship_id, Entity::default());
+        simulation.drain_ops();
+
+        simulation.client_update(&ship_id, ComponentUpdate::Position(position));
+        simulation.client_update(&unknown_id, ComponentUpdate::Position(position));
+        simulation.client_command(&ship_id, Command::UseSampler);
+        simulation.client_command(&unknown_id, Command::UseSampler);
+
+        let mut ops = simulation.drain_ops();
+
+        assert!(matches!(
+            ops.next(),
+            Some(Op::UpdateComponent(updated, ComponentUpdate::Position(_))) if updated == ship_id
+        ));
+        assert!(matches!(
+            ops.next(),
+            Some(Op::CommandRequest(commanded, Command::UseSampler)) if commanded == ship_id
+        ));
+        assert!(ops.next().is_none());
+        drop(ops);
+
+        //A resync replays the entity with the client updates applied
+        simulation.resync();
+
+        assert!(matches!(
+            simulation.drain_ops().next(),
+            Some(Op::AddEntity(_, entity)) if entity.position == Some(position)
+        ));
+    }
+
+    #[test]
+    fn client_responses_stop_once_the_ship_is_deleted() {
+        let mut simulation = Simulation::new(4);
+        let ship_id = serde_json::from_value::<Ship>(json!(1)).expect("Invalid ship id");
+        let first =
+            serde_json::from_value::<Module>(json!({ "Sampler": 1 })).expect("Invalid module id");
+        let second =
+            serde_json::from_value::<Module>(json!({ "Sampler": 2 })).expect("Invalid module id");
+
+        simulation.add_entity(&ship_id, Entity::default());
+
+        simulation.client_response(&ship_id, ClientResponse::CraftCompleted(first));
+        simulation.delete(&ship_id);
+        simulation.client_response(&ship_id, ClientResponse::CraftCompleted(second));
+
+        let mut responses = simulation.drain_client_responses();
+
+        assert!(matches!(
+            responses.next(),
+            Some((answered, ClientResponse::CraftCompleted(module_id)))
+                if answered == ship_id && module_id == first
+        ));
+        assert!(responses.next().is_none());
+        drop(responses);
+
+        assert!(matches!(
+            simulation.drain_ops().next_back(),
+            Some(Op::RemoveEntity(_))
+        ));
+    }
+}
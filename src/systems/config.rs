@@ -0,0 +1,40 @@
+use std::time::Duration;
+
+#[derive(Copy, Clone)]
+pub struct Config {
+    pub capacity: usize,
+    pub max_entities: usize,
+    pub cooldown_capacity: usize,
+
+    pub queue_capacity: usize,
+    //Systems whose full queue drops its oldest message instead of applying their own policy
+    pub drop_oldest: &'static [&'static str],
+    pub metrics_interval: Duration,
+
+    pub heartbeat_interval: u16,
+    pub position_interval: u32,
+
+    pub flush_interval: Duration,
+    pub flush_size: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            capacity: 100,
+            max_entities: 10_000,
+            cooldown_capacity: 1000,
+
+            queue_capacity: 10_000,
+            drop_oldest: &[],
+            metrics_interval: Duration::from_secs(60),
+
+            heartbeat_interval: 30_000,
+            position_interval: 900_000,
+
+            flush_interval: Duration::from_secs(5),
+            //Firestore commits at most 500 writes
+            flush_size: 500,
+        }
+    }
+}
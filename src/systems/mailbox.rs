@@ -0,0 +1,441 @@
+use std::collections::VecDeque;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+#[cfg(not(test))]
+const STALL_TIMEOUT: Duration = Duration::from_secs(5);
+#[cfg(test)]
+const STALL_TIMEOUT: Duration = Duration::from_millis(50);
+
+pub enum Envelope<T> {
+    Message(T),
+    Shutdown,
+}
+
+// What a full mailbox does with a new message.
+pub enum Overflow<T> {
+    Block,
+    DropOldest,
+    //Replace a queued message the function matches with the new one, block otherwise
+    Coalesce(fn(&T, &T) -> bool),
+}
+
+#[derive(Copy, Clone)]
+pub struct Metrics {
+    pub depth: usize,
+    pub capacity: usize,
+    pub high_water: usize,
+    pub dropped: u64,
+    pub coalesced: u64,
+    //Sends that waited longer than the stall timeout for room in the queue
+    pub stalled: u64,
+}
+
+struct Queue<T> {
+    capacity: usize,
+    overflow: Overflow<T>,
+
+    state: Mutex<State<T>>,
+    not_empty: Condvar,
+    not_full: Condvar,
+}
+
+struct State<T> {
+    messages: VecDeque<Envelope<T>>,
+
+    high_water: usize,
+    dropped: u64,
+    coalesced: u64,
+    stalled: u64,
+
+    //Set by the shutdown request, nothing receives the messages sent afterwards
+    closed: bool,
+}
+
+// Sending end of a bounded system queue, the queue survives the system being restarted.
+pub struct Mailbox<T> {
+    queue: Arc<Queue<T>>,
+}
+
+impl<T> Clone for Mailbox<T> {
+    fn clone(&self) -> Self {
+        Self {
+            queue: Arc::clone(&self.queue),
+        }
+    }
+}
+
+impl<T> Mailbox<T> {
+    pub fn new(capacity: usize, overflow: Overflow<T>) -> Self {
+        let state = State {
+            messages: VecDeque::with_capacity(capacity),
+
+            high_water: 0,
+            dropped: 0,
+            coalesced: 0,
+            stalled: 0,
+
+            closed: false,
+        };
+
+        Self {
+            queue: Arc::new(Queue {
+                capacity,
+                overflow,
+
+                state: Mutex::new(state),
+                not_empty: Condvar::new(),
+                not_full: Condvar::new(),
+            }),
+        }
+    }
+
+    pub fn receiver(&self) -> Receiver<T> {
+        Receiver {
+            queue: Arc::clone(&self.queue),
+        }
+    }
+
+    // Only DropOldest and closed mailboxes lose messages, Block waits as long as the receiver needs.
+    pub fn send(&self, mut message: T) {
+        let stall_deadline = Instant::now() + STALL_TIMEOUT;
+        let mut stalled = false;
+
+        let mut state = self.queue.lock();
+
+        while state.messages.len() >= self.queue.capacity || state.closed {
+            if state.closed {
+                state.dropped += 1;
+
+                return;
+            }
+
+            match &self.queue.overflow {
+                Overflow::Block => {}
+                Overflow::DropOldest => {
+                    if state.drop_oldest() {
+                        break;
+                    }
+                }
+                Overflow::Coalesce(matches) => match state.coalesce(message, *matches) {
+                    Ok(()) => return,
+                    Err(returned) => message = returned,
+                },
+            }
+
+            let now = Instant::now();
+
+            //Reported with the queue metrics, the message is still delivered
+            if !stalled && now >= stall_deadline {
+                state.stalled += 1;
+                stalled = true;
+            }
+
+            state = if stalled {
+                self.queue
+                    .not_full
+                    .wait(state)
+                    .unwrap_or_else(PoisonError::into_inner)
+            } else {
+                self.queue
+                    .not_full
+                    .wait_timeout(state, stall_deadline - now)
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .0
+            };
+        }
+
+        state.messages.push_back(Envelope::Message(message));
+        state.high_water = state.high_water.max(state.messages.len());
+
+        self.queue.not_empty.notify_one();
+    }
+
+    // Ask the receiving system to stop once it handled the messages already sent.
+    pub fn shutdown(&self) {
+        let mut state = self.queue.lock();
+
+        if state.closed {
+            return;
+        }
+
+        //Never dropped nor blocked, the queue may grow past its capacity by one
+        state.messages.push_back(Envelope::Shutdown);
+        state.closed = true;
+
+        self.queue.not_empty.notify_one();
+        //Blocked senders would wait forever on a stopped system
+        self.queue.not_full.notify_all();
+    }
+
+    pub fn metrics(&self) -> Metrics {
+        let state = self.queue.lock();
+
+        Metrics {
+            depth: state.messages.len(),
+            capacity: self.queue.capacity,
+            high_water: state.high_water,
+            dropped: state.dropped,
+            coalesced: state.coalesced,
+            stalled: state.stalled,
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    queue: Arc<Queue<T>>,
+}
+
+impl<T> Receiver<T> {
+    pub fn recv(&self) -> Envelope<T> {
+        let mut state = self.queue.lock();
+
+        loop {
+            if let Some(envelope) = self.pop(&mut state) {
+                return envelope;
+            }
+
+            state = self
+                .queue
+                .not_empty
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Envelope<T>, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+
+        let mut state = self.queue.lock();
+
+        loop {
+            if let Some(envelope) = self.pop(&mut state) {
+                return Ok(envelope);
+            }
+
+            let now = Instant::now();
+
+            if now >= deadline {
+                return Err(RecvTimeoutError::Timeout);
+            }
+
+            state = self
+                .queue
+                .not_empty
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+    }
+
+    pub fn try_recv(&self) -> Option<Envelope<T>> {
+        let mut state = self.queue.lock();
+
+        self.pop(&mut state)
+    }
+
+    fn pop(&self, state: &mut State<T>) -> Option<Envelope<T>> {
+        let envelope = state.messages.pop_front()?;
+
+        self.queue.not_full.notify_one();
+
+        Some(envelope)
+    }
+}
+
+impl<T> Queue<T> {
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<T> State<T> {
+    //Returns false if only the shutdown request is queued
+    fn drop_oldest(&mut self) -> bool {
+        let index = self
+            .messages
+            .iter()
+            .position(|envelope| matches!(envelope, Envelope::Message(_)));
+
+        match index {
+            Some(index) => {
+                self.messages.remove(index);
+                self.dropped += 1;
+
+                true
+            }
+            None => false,
+        }
+    }
+
+    //The queued message keeps its place, only its content is replaced
+    fn coalesce(&mut self, message: T, matches: fn(&T, &T) -> bool) -> Result<(), T> {
+        let index = self.messages.iter().position(|envelope| match envelope {
+            Envelope::Message(queued) => matches(queued, &message),
+            Envelope::Shutdown => false,
+        });
+
+        match index {
+            Some(index) => {
+                self.messages[index] = Envelope::Message(message);
+                self.coalesced += 1;
+
+                Ok(())
+            }
+            None => Err(message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn message<T>(envelope: Option<Envelope<T>>) -> Option<T> {
+        match envelope {
+            Some(Envelope::Message(message)) => Some(message),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn messages_are_received_in_order_before_shutdown() {
+        let mailbox = Mailbox::new(4, Overflow::Block);
+        let receiver = mailbox.receiver();
+
+        mailbox.send(1);
+        mailbox.send(2);
+        mailbox.shutdown();
+
+        assert_eq!(message(receiver.try_recv()), Some(1));
+        assert_eq!(message(receiver.try_recv()), Some(2));
+        assert!(matches!(receiver.try_recv(), Some(Envelope::Shutdown)));
+        assert!(receiver.try_recv().is_none());
+    }
+
+    #[test]
+    fn drop_oldest_makes_room_for_the_new_message() {
+        let mailbox = Mailbox::new(2, Overflow::DropOldest);
+        let receiver = mailbox.receiver();
+
+        mailbox.send(1);
+        mailbox.send(2);
+        mailbox.send(3);
+
+        let metrics = mailbox.metrics();
+
+        assert_eq!(metrics.depth, 2);
+        assert_eq!(metrics.dropped, 1);
+        assert_eq!(message(receiver.try_recv()), Some(2));
+        assert_eq!(message(receiver.try_recv()), Some(3));
+    }
+
+    #[test]
+    fn stalled_send_is_counted_and_still_delivered() {
+        let mailbox = Mailbox::new(1, Overflow::Block);
+        let receiver = mailbox.receiver();
+
+        mailbox.send(1);
+
+        let sender = {
+            let mailbox = mailbox.clone();
+
+            thread::spawn(move || mailbox.send(2))
+        };
+
+        thread::sleep(STALL_TIMEOUT * 2);
+
+        assert_eq!(mailbox.metrics().stalled, 1);
+        assert_eq!(message(receiver.try_recv()), Some(1));
+
+        sender.join().expect("Sender panicked");
+
+        let metrics = mailbox.metrics();
+
+        assert_eq!(metrics.dropped, 0);
+        assert_eq!(message(receiver.try_recv()), Some(2));
+    }
+
+    #[test]
+    fn blocked_send_resumes_once_received() {
+        let mailbox = Mailbox::new(1, Overflow::Block);
+        let receiver = mailbox.receiver();
+
+        mailbox.send(1);
+
+        let sender = {
+            let mailbox = mailbox.clone();
+
+            thread::spawn(move || mailbox.send(2))
+        };
+
+        //Waits on the sender to be blocked
+        thread::sleep(STALL_TIMEOUT / 5);
+
+        assert_eq!(message(receiver.try_recv()), Some(1));
+
+        sender.join().expect("Sender panicked");
+
+        assert_eq!(message(receiver.try_recv()), Some(2));
+        assert_eq!(mailbox.metrics().stalled, 0);
+    }
+
+    #[test]
+    fn shutdown_releases_blocked_senders() {
+        let mailbox = Mailbox::new(1, Overflow::Block);
+        let receiver = mailbox.receiver();
+
+        mailbox.send(1);
+
+        let sender = {
+            let mailbox = mailbox.clone();
+
+            thread::spawn(move || mailbox.send(2))
+        };
+
+        thread::sleep(STALL_TIMEOUT / 5);
+
+        mailbox.shutdown();
+
+        sender.join().expect("Sender panicked");
+
+        assert_eq!(mailbox.metrics().dropped, 1);
+        assert_eq!(message(receiver.try_recv()), Some(1));
+        assert!(matches!(receiver.try_recv(), Some(Envelope::Shutdown)));
+        assert!(receiver.try_recv().is_none());
+    }
+
+    #[test]
+    fn coalesce_replaces_the_matching_message_in_place() {
+        let mailbox = Mailbox::new(
+            2,
+            Overflow::Coalesce(|queued: &(u8, u32), message| queued.0 == message.0),
+        );
+        let receiver = mailbox.receiver();
+
+        mailbox.send((1, 1));
+        mailbox.send((2, 1));
+        mailbox.send((1, 2));
+
+        let metrics = mailbox.metrics();
+
+        assert_eq!(metrics.coalesced, 1);
+        assert_eq!(metrics.dropped, 0);
+        assert_eq!(message(receiver.try_recv()), Some((1, 2)));
+        assert_eq!(message(receiver.try_recv()), Some((2, 1)));
+    }
+
+    #[test]
+    fn recv_timeout_expires_on_an_empty_queue() {
+        let mailbox = Mailbox::<u32>::new(1, Overflow::Block);
+        let receiver = mailbox.receiver();
+
+        assert!(matches!(
+            receiver.recv_timeout(Duration::from_millis(1)),
+            Err(RecvTimeoutError::Timeout)
+        ));
+        assert_eq!(mailbox.metrics().high_water, 0);
+    }
+}
@@ -0,0 +1,49 @@
+pub mod config;
+pub mod mailbox;
+pub mod registry;
+pub mod runner;
+pub mod supervisor;
+
+use crate::systems::mailbox::Overflow;
+use crate::systems::registry::Registry;
+use std::time::Duration;
+
+pub trait System: Sized + Send + 'static {
+    type Message: Send + 'static;
+
+    const NAME: &'static str;
+
+    // Register resources shared with other systems, called once before any system starts.
+    fn register(_registry: &mut Registry) {}
+
+    // Called when the system starts and again after every restart.
+    fn init(registry: &Registry) -> Self;
+
+    // What the mailbox does once it holds as many messages as the configured queue capacity.
+    fn overflow() -> Overflow<Self::Message> {
+        Overflow::Block
+    }
+
+    fn handle_message(&mut self, message: Self::Message);
+
+    // Messages already queued are handed over together, in the order they were sent.
+    fn handle_batch(&mut self, messages: Vec<Self::Message>) {
+        for message in messages {
+            self.handle_message(message);
+        }
+    }
+
+    // Systems with a frame time are ticked at that rate between messages.
+    fn frame_time(&self) -> Option<Duration> {
+        None
+    }
+
+    fn tick(&mut self) {}
+
+    // Last chance to flush state, called after the shutdown request.
+    fn shutdown(&mut self) {}
+
+    fn stop(registry: &Registry) {
+        registry.mailbox::<Self>().shutdown();
+    }
+}
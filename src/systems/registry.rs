@@ -0,0 +1,39 @@
+use crate::systems::mailbox::Mailbox;
+use crate::systems::System;
+use std::any::{type_name, Any, TypeId};
+use std::collections::HashMap;
+
+// Shared resources looked up by type, like stores and mailboxes.
+pub struct Registry {
+    resources: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self {
+            resources: HashMap::new(),
+        }
+    }
+
+    pub fn insert<R>(&mut self, resource: R)
+    where
+        R: Any + Send + Sync,
+    {
+        self.resources.insert(TypeId::of::<R>(), Box::new(resource));
+    }
+
+    pub fn get<R>(&self) -> R
+    where
+        R: Any + Clone,
+    {
+        self.resources
+            .get(&TypeId::of::<R>())
+            .and_then(|resource| resource.downcast_ref::<R>())
+            .cloned()
+            .unwrap_or_else(|| panic!("Resource {} is not registered", type_name::<R>()))
+    }
+
+    pub fn mailbox<S: System>(&self) -> Mailbox<S::Message> {
+        self.get::<Mailbox<S::Message>>()
+    }
+}
@@ -0,0 +1,230 @@
+use crate::systems::config::Config;
+use crate::systems::mailbox::{Envelope, Mailbox, Metrics, Overflow, Receiver};
+use crate::systems::registry::Registry;
+use crate::systems::supervisor::Supervisor;
+use crate::systems::System;
+use std::sync::mpsc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::time::Instant;
+
+type Starter = Box<dyn FnOnce(&Arc<Registry>, &mut Supervisor)>;
+
+trait Queue {
+    fn metrics(&self) -> Metrics;
+}
+
+impl<T> Queue for Mailbox<T> {
+    fn metrics(&self) -> Metrics {
+        Mailbox::metrics(self)
+    }
+}
+
+pub struct Builder {
+    registry: Registry,
+    starters: Vec<Starter>,
+    queues: Vec<(&'static str, Box<dyn Queue>)>,
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Self {
+            registry: Registry::new(),
+            starters: Vec::new(),
+            queues: Vec::new(),
+        }
+    }
+
+    pub fn resource<R>(mut self, resource: R) -> Self
+    where
+        R: Send + Sync + 'static,
+    {
+        self.registry.insert(resource);
+
+        self
+    }
+
+    // Systems are started in the order they are added and stopped in reverse.
+    pub fn system<S: System>(mut self) -> Self {
+        let config = self.registry.get::<Config>();
+
+        let overflow = if config.drop_oldest.contains(&S::NAME) {
+            Overflow::DropOldest
+        } else {
+            S::overflow()
+        };
+
+        let mailbox = Mailbox::<S::Message>::new(config.queue_capacity, overflow);
+
+        self.queues.push((S::NAME, Box::new(mailbox.clone())));
+
+        self.registry.insert(mailbox);
+
+        S::register(&mut self.registry);
+
+        self.starters.push(Box::new(start::<S>));
+
+        self
+    }
+
+    // Every resource is registered before the first system starts.
+    pub fn start(self) -> Systems {
+        let registry = Arc::new(self.registry);
+        let mut supervisor = Supervisor::new();
+
+        for starter in self.starters {
+            starter(&registry, &mut supervisor);
+        }
+
+        Systems {
+            registry,
+            supervisor,
+            queues: self.queues,
+        }
+    }
+}
+
+pub struct Systems {
+    registry: Arc<Registry>,
+    supervisor: Supervisor,
+    queues: Vec<(&'static str, Box<dyn Queue>)>,
+}
+
+impl Systems {
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    pub fn on_restart<F>(&mut self, callback: F)
+    where
+        F: Fn() + 'static,
+    {
+        self.supervisor.on_restart(callback);
+    }
+
+    pub fn run(&mut self, shutdown: &mpsc::Receiver<()>) {
+        let interval = self.registry.get::<Config>().metrics_interval;
+
+        while !self.supervisor.run(shutdown, interval) {
+            self.report_queues();
+        }
+    }
+
+    //Only queues with a backlog, that lost messages or kept senders waiting are reported
+    fn report_queues(&self) {
+        for (name, queue) in self.queues.iter() {
+            let metrics = queue.metrics();
+
+            if metrics.depth == 0
+                && metrics.dropped == 0
+                && metrics.coalesced == 0
+                && metrics.stalled == 0
+            {
+                continue;
+            }
+
+            println!(
+                "Queue {}: depth {}/{}, high water {}, {} dropped, {} coalesced, {} stalled",
+                name,
+                metrics.depth,
+                metrics.capacity,
+                metrics.high_water,
+                metrics.dropped,
+                metrics.coalesced,
+                metrics.stalled
+            );
+        }
+    }
+
+    pub fn shutdown(&mut self) {
+        self.supervisor.shutdown();
+
+        //Messages sent to systems already stopped are counted as dropped
+        self.report_queues();
+    }
+}
+
+fn start<S: System>(registry: &Arc<Registry>, supervisor: &mut Supervisor) {
+    let mailbox = registry.mailbox::<S>();
+
+    let stop = {
+        let registry = Arc::clone(registry);
+
+        move || S::stop(&registry)
+    };
+
+    let registry = Arc::clone(registry);
+
+    supervisor.supervise(S::NAME, &mailbox, stop, move |channel| {
+        let system = S::init(&registry);
+
+        move || run(system, channel)
+    });
+}
+
+fn run<S: System>(mut system: S, channel: Receiver<S::Message>) {
+    let mut next_frame = Instant::now();
+
+    loop {
+        let result = match system.frame_time() {
+            Some(frame_time) => {
+                if Instant::now() >= next_frame {
+                    let before_frame = Instant::now();
+
+                    system.tick();
+
+                    let frame_duration = before_frame.elapsed();
+
+                    if frame_duration > frame_time {
+                        println!(
+                            "The frame took {:?}, too long couldn't sleep",
+                            frame_duration
+                        );
+                    }
+
+                    next_frame = before_frame + frame_time;
+                }
+
+                channel.recv_timeout(next_frame.saturating_duration_since(Instant::now()))
+            }
+            None => Ok(channel.recv()),
+        };
+
+        match result {
+            Ok(Envelope::Message(message)) => {
+                if !handle_batch(&mut system, &channel, message) {
+                    break;
+                }
+            }
+            Ok(Envelope::Shutdown) => break,
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
+    system.shutdown();
+}
+
+//Returns false if a shutdown was requested
+fn handle_batch<S: System>(
+    system: &mut S,
+    channel: &Receiver<S::Message>,
+    first: S::Message,
+) -> bool {
+    let mut messages = vec![first];
+    let mut running = true;
+
+    while let Some(envelope) = channel.try_recv() {
+        match envelope {
+            Envelope::Message(message) => messages.push(message),
+            Envelope::Shutdown => {
+                running = false;
+                break;
+            }
+        }
+    }
+
+    system.handle_batch(messages);
+
+    running
+}
@@ -0,0 +1,177 @@
+use crate::systems::mailbox::{Mailbox, Receiver};
+use std::any::Any;
+use std::sync::mpsc;
+use std::sync::mpsc::{RecvTimeoutError, Sender};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+const WATCH_INTERVAL: Duration = Duration::from_millis(100);
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+struct Child {
+    name: &'static str,
+    handle: Option<JoinHandle<()>>,
+    start: Box<dyn FnMut() -> JoinHandle<()>>,
+    stop: Option<Box<dyn FnOnce()>>,
+}
+
+struct ExitGuard {
+    index: usize,
+    exits: Sender<usize>,
+}
+
+impl Drop for ExitGuard {
+    fn drop(&mut self) {
+        let _ = self.exits.send(self.index);
+    }
+}
+
+pub struct Supervisor {
+    children: Vec<Child>,
+
+    exits_tx: Sender<usize>,
+    exits_rx: mpsc::Receiver<usize>,
+
+    on_restart: Option<Box<dyn Fn()>>,
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        let (exits_tx, exits_rx) = mpsc::channel();
+
+        Self {
+            children: Vec::new(),
+
+            exits_tx,
+            exits_rx,
+
+            on_restart: None,
+        }
+    }
+
+    // Called after a system was restarted, to rebuild state it lost.
+    pub fn on_restart<F>(&mut self, callback: F)
+    where
+        F: Fn() + 'static,
+    {
+        self.on_restart = Some(Box::new(callback));
+    }
+
+    pub fn supervise<T, S, F, R>(
+        &mut self,
+        name: &'static str,
+        mailbox: &Mailbox<T>,
+        stop: S,
+        mut factory: F,
+    ) where
+        T: Send + 'static,
+        S: FnOnce() + 'static,
+        F: FnMut(Receiver<T>) -> R + 'static,
+        R: FnOnce() + Send + 'static,
+    {
+        let index = self.children.len();
+        let exits = self.exits_tx.clone();
+        let mailbox = mailbox.clone();
+
+        let start = move || {
+            let update_loop = factory(mailbox.receiver());
+
+            let guard = ExitGuard {
+                index,
+                exits: exits.clone(),
+            };
+
+            thread::Builder::new()
+                .name(name.to_string())
+                .spawn(move || {
+                    let _guard = guard;
+
+                    update_loop();
+                })
+                .expect("Failed to spawn system thread")
+        };
+
+        let mut child = Child {
+            name,
+            handle: None,
+            start: Box::new(start),
+            stop: Some(Box::new(stop)),
+        };
+
+        child.handle = Some((child.start)());
+
+        self.children.push(child);
+    }
+
+    // Restart panicked systems for the duration, returns true if a shutdown was signaled.
+    pub fn run(&mut self, shutdown: &mpsc::Receiver<()>, duration: Duration) -> bool {
+        let deadline = Instant::now() + duration;
+
+        loop {
+            if shutdown.try_recv().is_ok() {
+                return true;
+            }
+
+            if Instant::now() >= deadline {
+                return false;
+            }
+
+            let index = match self.exits_rx.recv_timeout(WATCH_INTERVAL) {
+                Ok(index) => index,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => return true,
+            };
+
+            let child = &mut self.children[index];
+
+            let handle = match child.handle.take() {
+                Some(handle) => handle,
+                None => continue,
+            };
+
+            if let Err(payload) = handle.join() {
+                println!(
+                    "System {} panicked: {}, restarting",
+                    child.name,
+                    panic_message(&*payload)
+                );
+
+                thread::sleep(RESTART_DELAY);
+
+                child.handle = Some((child.start)());
+
+                if let Some(callback) = &self.on_restart {
+                    callback();
+                }
+            } else {
+                println!("System {} terminated", child.name);
+            }
+        }
+    }
+
+    // Stop systems in reverse order of creation, so that no system outlives the ones sending to it.
+    pub fn shutdown(&mut self) {
+        for child in self.children.iter_mut().rev() {
+            if let Some(stop) = child.stop.take() {
+                stop();
+            }
+
+            if let Some(handle) = child.handle.take() {
+                if handle.join().is_err() {
+                    println!("System {} panicked before shutting down", child.name);
+                }
+            }
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown cause"
+    }
+}